@@ -0,0 +1,181 @@
+//! Writes that concurrent invocations never see half done. A file is written
+//! next to its destination and renamed over it, and a read-modify-write cycle
+//! holds an advisory lock so two invocations cannot lose each other's update.
+
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::error::Error;
+
+/// How long to wait for another invocation to release a lock
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_POLL: Duration = Duration::from_millis(20);
+/// Names tried for the temporary file before giving up
+const TEMP_ATTEMPTS: u32 = 100;
+
+/// Replace the content of a file, so a crash leaves either the old or the new
+/// content but never a truncated file. The permissions of the file replaced
+/// are kept, and a symlink is kept by replacing the file it leads to.
+pub fn write(path: &Path, content: impl AsRef<[u8]>) -> Result<(), Error> {
+    let path = resolve(path)?;
+    let (temp, mut file) = create_temp(&path)?;
+    let written = (|| -> io::Result<()> {
+        file.write_all(content.as_ref())?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+    // The rename itself is only durable once the directory is synced
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+/// The file a path leads to, following symlinks, or the path itself when
+/// there is no such file yet
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Ok(path.to_path_buf())
+        }
+        resolved => resolved,
+    }
+}
+
+/// Create a hidden file in the same directory, as a rename does not cross
+/// file systems. A file left behind by a killed process whose id was since
+/// reused is passed over for the next name rather than failing the write.
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        let temp = temp_file(path, attempt);
+        match OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Err(err)
+                if err.kind() == io::ErrorKind::AlreadyExists
+                    && attempt + 1 < TEMP_ATTEMPTS =>
+            {
+                attempt += 1;
+            }
+            file => return Ok((temp, file?)),
+        }
+    }
+}
+
+/// The name of a temporary file, after the process so concurrent writers
+/// never share one
+fn temp_file(path: &Path, attempt: u32) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.{}.tmp", process::id(), attempt));
+    path.with_file_name(name)
+}
+
+/// An exclusive advisory lock on a file, released when dropped. The lock is
+/// taken on `<file>.lock` rather than the file itself, since renaming a new
+/// file into place would leave waiting invocations locking the old one.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<Self, Error> {
+        let lock_file = lock_file(&resolve(path)?);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_file)?;
+        let start = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Self { _file: file }),
+                Err(TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                    debug!("waiting for the lock on {:?}", lock_file);
+                    thread::sleep(LOCK_POLL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(Error::Locked(path.to_path_buf(), timeout))
+                }
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+fn lock_file(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn write_replaces() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.join("config.toml");
+        write(&file, "old").unwrap();
+        write(&file, "new").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn write_passes_over_leftover_temp_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.join("config.toml");
+        let leftover = temp_file(&file, 0);
+        fs::write(&leftover, "half written").unwrap();
+        write(&file, "new").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&leftover).unwrap(), "half written");
+    }
+
+    #[test]
+    fn write_through_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.join("dotfiles/config.toml");
+        let link = temp_dir.join("config.toml");
+        fs::create_dir(temp_dir.join("dotfiles")).unwrap();
+        fs::write(&file, "old").unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        write(&link, "new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(fs::read_dir(temp_dir.join("dotfiles")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn lock_times_out() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.join("config.toml");
+        let lock = Lock::acquire(&file, LOCK_TIMEOUT).unwrap();
+        assert!(matches!(
+            Lock::acquire(&file, Duration::from_millis(50)),
+            Err(Error::Locked(_, _))
+        ));
+        drop(lock);
+        Lock::acquire(&file, Duration::ZERO).unwrap();
+    }
+}
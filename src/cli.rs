@@ -1,18 +1,24 @@
 use std::path::PathBuf;
 
 use clap::{
-    crate_authors, crate_description, crate_name, value_parser, Arg,
+    crate_authors, crate_description, crate_name, value_parser, Arg, ArgAction,
     ArgMatches, Command,
 };
 use tracing::Level;
 
+use crate::{list::Format, shell::Shell, workspace::Inference};
+
 pub fn parse() -> ArgMatches {
+    command().get_matches()
+}
+
+fn command() -> Command {
     let log_level = Arg::new("log_level")
         .short('l')
         .long("log-level")
         .value_parser(value_parser!(Level))
         .global(true)
-        .help("Set the log level for stdout");
+        .help("Set the log level for stderr");
 
     let config_file = Arg::new("config_file")
         .short('c')
@@ -21,6 +27,19 @@ pub fn parse() -> ArgMatches {
         .global(true)
         .default_value("$XDG_CONFIG_HOME/projectfinder.toml");
 
+    let verbose = Arg::new("verbose")
+        .short('v')
+        .long("verbose")
+        .action(ArgAction::SetTrue)
+        .global(true)
+        .help("List every path that could not be read while searching");
+
+    let cd_file = Arg::new("cd_file")
+        .long("cd-file")
+        .value_parser(value_parser!(PathBuf))
+        .global(true)
+        .help("Write the chosen project to a file instead of stdout");
+
     let init_command = Command::new("init")
         .about("Initialize a new config file in the current directory");
 
@@ -53,6 +72,11 @@ pub fn parse() -> ArgMatches {
                 .help("Path to a directory containing projects")
                 .value_parser(value_parser!(PathBuf))
                 .required(true),
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Add the directory even if it is within or contains another directory of the workspace"),
         ]);
     let remove_directory = Command::new("remove")
         .aliases(["rm", "r"])
@@ -71,16 +95,158 @@ pub fn parse() -> ArgMatches {
         .subcommands([add_directory, remove_directory])
         .subcommand_required(true);
 
+    let threads = Arg::new("threads")
+        .short('j')
+        .long("threads")
+        .value_parser(value_parser!(usize))
+        .help("Amount of threads used to search; 0 uses one per core");
+
     // Search
     let search = Command::new("search")
         .aliases(["s"])
         .about("Search a workspace; Run without arguments to infer a workspace")
-        .args([Arg::new("name")]);
+        .args([
+            Arg::new("name"),
+            Arg::new("query")
+                .num_args(1..)
+                .help("Jump straight to the project if it clearly matches best"),
+            threads.clone(),
+            Arg::new("rescan")
+                .long("rescan")
+                .action(ArgAction::SetTrue)
+                .help("Ignore the index and search every directory again"),
+            Arg::new("filter")
+                .short('f')
+                .long("filter")
+                .value_name("QUERY")
+                .help("Print the projects matching a query instead of picking one"),
+            Arg::new("print0")
+                .long("print0")
+                .action(ArgAction::SetTrue)
+                .requires("filter")
+                .help("Separate filtered projects with NUL instead of newlines"),
+            Arg::new("exclude")
+                .short('e')
+                .long("exclude")
+                .value_name("GLOB")
+                .action(ArgAction::Append)
+                .help("Skip directories matching a glob in addition to the configured ones"),
+            Arg::new("max_depth")
+                .long("max-depth")
+                .value_name("DEPTH")
+                .value_parser(value_parser!(usize))
+                .help("Search no deeper than a depth below each directory"),
+            Arg::new("min_depth")
+                .long("min-depth")
+                .value_name("DEPTH")
+                .value_parser(value_parser!(usize))
+                .help("Only find projects at least this many levels below each directory"),
+            Arg::new("infer")
+                .long("infer")
+                .value_parser(value_parser!(Inference))
+                .conflicts_with("name")
+                .help("Search the most specific workspace containing the current directory, ask which, or search all of them"),
+        ]);
+
+    // Manage the project index
+    let rebuild_index = Command::new("rebuild")
+        .about("Search workspaces from scratch and replace their index")
+        .args([
+            Arg::new("name").help(
+                "Name of a workspace; Rebuilds all workspaces if omitted",
+            ),
+            threads.clone(),
+        ]);
+    let index_span = Command::new("index")
+        .aliases(["i"])
+        .about("Manage the cached project index")
+        .subcommands([rebuild_index])
+        .subcommand_required(true);
+
+    let list = Command::new("list")
+        .aliases(["ls", "l"])
+        .about("List workspaces and their directories")
+        .args([
+            Arg::new("name")
+                .help("Name of a workspace; Lists all workspaces if omitted"),
+            Arg::new("projects")
+                .short('p')
+                .long("projects")
+                .action(ArgAction::SetTrue)
+                .help("Search and list the projects of every directory"),
+            Arg::new("format")
+                .long("format")
+                .value_parser(Format::NAMES)
+                .default_value("table"),
+            threads.clone(),
+        ]);
+
+    // Manage the selection history
+    let clear_history =
+        Command::new("clear").about("Forget every project chosen so far");
+    let history_span = Command::new("history")
+        .about("Manage the history used to rank frequently chosen projects")
+        .subcommands([clear_history])
+        .subcommand_required(true);
+
+    let daemon = Command::new("daemon")
+        .about("Watch all workspaces and answer searches from memory")
+        .args([threads]);
+
+    let shell_init = Command::new("shell-init")
+        .about("Print a shell function that changes into the chosen project")
+        .args([
+            Arg::new("shell").value_parser(Shell::NAMES).required(true),
+            Arg::new("cmd")
+                .long("cmd")
+                .default_value("pf")
+                .help("Name of the shell function"),
+            Arg::new("bind")
+                .long("bind")
+                .value_name("KEY")
+                .help("Bind the picker to a key such as 'ctrl-g'"),
+        ]);
 
     Command::new(crate_name!())
         .about(crate_description!())
         .author(crate_authors!())
-        .subcommands([init_command, workspace_span, directory_span, search])
-        .args([log_level, config_file])
-        .get_matches()
+        .subcommands([
+            init_command,
+            workspace_span,
+            directory_span,
+            search,
+            index_span,
+            list,
+            history_span,
+            daemon,
+            shell_init,
+        ])
+        .args([
+            log_level,
+            verbose,
+            config_file,
+            cd_file,
+            Arg::new("query").num_args(1..).help(
+                "Search the current workspace, jumping straight to the project if it clearly matches best; A query starting with a command name or alias such as 'ls' runs that command instead, so pass such a query after '--'",
+            ),
+        ])
+        .args_conflicts_with_subcommands(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_named_like_a_command() {
+        let matches = command().try_get_matches_from(["pf", "ls"]).unwrap();
+        assert_eq!(matches.subcommand_name(), Some("list"));
+
+        let matches = command()
+            .try_get_matches_from(["pf", "--", "ls", "api"])
+            .unwrap();
+        assert_eq!(matches.subcommand_name(), None);
+        let query: Vec<&String> = matches.get_many("query").unwrap().collect();
+        assert_eq!(query, ["ls", "api"]);
+    }
 }
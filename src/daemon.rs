@@ -0,0 +1,355 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{error::Error, index::Index};
+
+/// Time to wait for more events after a change to a workspace, so a burst of
+/// changes such as a fresh clone results in a single reindex
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Time a search waits for the daemon to answer before searching by itself,
+/// and the daemon waits for a query before moving on to the next client
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Projects of every workspace, as last indexed by the daemon
+type Projects = Arc<Mutex<HashMap<String, Vec<PathBuf>>>>;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Request {
+    Search { workspace: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Projects(Vec<PathBuf>),
+    Error(String),
+}
+
+/// Ask a running daemon for the projects of a workspace. Returns `None` when
+/// no daemon is listening or it could not answer in time, in which case the
+/// caller is expected to search the filesystem itself.
+pub fn query(socket: &Path, workspace: &str) -> Option<Vec<PathBuf>> {
+    let stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(err) => {
+            debug!("no daemon listening on {:?}: {}", socket, err);
+            return None;
+        }
+    };
+    match request(
+        stream,
+        &Request::Search {
+            workspace: workspace.to_string(),
+        },
+    ) {
+        Ok(Response::Projects(projects)) => Some(projects),
+        Ok(Response::Error(err)) => {
+            warn!("daemon could not search {}: {}", workspace, err);
+            None
+        }
+        Err(Error::IOError(err))
+            if matches!(
+                err.kind(),
+                ErrorKind::WouldBlock | ErrorKind::TimedOut
+            ) =>
+        {
+            debug!("no daemon answering on {:?}: {}", socket, err);
+            None
+        }
+        Err(err) => {
+            warn!("could not query daemon: {}", err);
+            None
+        }
+    }
+}
+
+fn request(stream: UnixStream, request: &Request) -> Result<Response, Error> {
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Watches every directory of every workspace and keeps their projects up to
+/// date, answering queries on a Unix socket until the process is killed
+pub struct Daemon<I, N>
+where
+    I: Fn(&str) -> Result<Index, Error>,
+    N: Fn() -> Result<Vec<String>, Error>,
+{
+    index_workspace: I,
+    workspace_names: N,
+    config_file: PathBuf,
+    inotify: Inotify,
+    projects: Projects,
+    /// Directories of every workspace, used to find the workspaces to reindex
+    directories: HashMap<String, HashSet<PathBuf>>,
+    watches: HashMap<WatchDescriptor, PathBuf>,
+    watched: HashSet<PathBuf>,
+}
+
+impl<I, N> Daemon<I, N>
+where
+    I: Fn(&str) -> Result<Index, Error>,
+    N: Fn() -> Result<Vec<String>, Error>,
+{
+    pub fn new(
+        config_file: PathBuf,
+        index_workspace: I,
+        workspace_names: N,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            index_workspace,
+            workspace_names,
+            config_file,
+            inotify: Inotify::init()?,
+            projects: Arc::default(),
+            directories: HashMap::new(),
+            watches: HashMap::new(),
+            watched: HashSet::new(),
+        })
+    }
+
+    pub fn run(mut self, socket: &Path) -> Result<(), Error> {
+        let listener = bind(socket)?;
+        info!("listening on {:?}", socket);
+        let projects = self.projects.clone();
+        thread::spawn(move || serve(listener, projects));
+
+        // Watch the directory of the config file rather than the file itself,
+        // so the watch survives the file being replaced
+        let config_dir = self
+            .config_file
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let config_watch = self.inotify.watches().add(
+            &config_dir,
+            WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+        )?;
+        self.reindex_all()?;
+
+        let mut buffer = [0; 4096];
+        // When each workspace with changes and the config are due to be
+        // reindexed, so a burst of changes to one workspace holds back
+        // neither the others nor the daemon
+        let mut pending: HashMap<String, Instant> = HashMap::new();
+        let mut config_pending = None;
+        loop {
+            let next = pending.values().chain(&config_pending).min().copied();
+            let events = match next {
+                None => Some(self.inotify.read_events_blocking(&mut buffer)?),
+                Some(deadline) => {
+                    thread::sleep(
+                        deadline.saturating_duration_since(Instant::now()),
+                    );
+                    match self.inotify.read_events(&mut buffer) {
+                        Ok(events) => Some(events),
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => None,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            };
+            let deadline = Instant::now() + DEBOUNCE;
+            let mut changed = HashSet::new();
+            for event in events.into_iter().flatten() {
+                if event.wd == config_watch {
+                    if event.name == self.config_file.file_name() {
+                        config_pending = Some(deadline);
+                    }
+                } else if event.mask.contains(EventMask::IGNORED) {
+                    // The directory is gone, so it has to be watched again
+                    // should it come back
+                    if let Some(directory) = self.watches.remove(&event.wd) {
+                        self.watched.remove(&directory);
+                    }
+                } else if let Some(directory) = self.watches.get(&event.wd) {
+                    trace!("{:?} changed: {:?}", directory, event.mask);
+                    changed.insert(directory.clone());
+                }
+            }
+            for (name, directories) in &self.directories {
+                if !directories.is_disjoint(&changed) {
+                    pending.insert(name.clone(), deadline);
+                }
+            }
+
+            let now = Instant::now();
+            if config_pending.is_some_and(|due| due <= now) {
+                config_pending = None;
+                info!("config changed; reindexing all workspaces");
+                // A config that cannot be read, say while it is being saved,
+                // leaves the workspaces as they were until it changes again
+                match self.reindex_all() {
+                    Ok(()) => pending.clear(),
+                    Err(err) => error!("could not read config: {}", err),
+                }
+            }
+            let due: Vec<String> = pending
+                .iter()
+                .filter(|(_, due)| **due <= now)
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in due {
+                pending.remove(&name);
+                self.reindex(&name);
+            }
+        }
+    }
+
+    fn reindex_all(&mut self) -> Result<(), Error> {
+        let names = (self.workspace_names)()?;
+        self.directories.retain(|name, _| names.contains(name));
+        self.projects
+            .lock()
+            .map_err(|_| Error::PoisonedDaemon)?
+            .retain(|name, _| names.contains(name));
+        for name in names {
+            self.reindex(&name);
+        }
+        Ok(())
+    }
+
+    /// Reindex a single workspace, keeping the previous projects if that
+    /// fails so one broken workspace does not take down the daemon
+    fn reindex(&mut self, name: &str) {
+        debug!("reindexing {}", name);
+        let index = match (self.index_workspace)(name) {
+            Ok(index) => index,
+            Err(err) => {
+                error!("could not index {}: {}", name, err);
+                return;
+            }
+        };
+        for directory in index.directories.keys() {
+            self.watch(directory);
+        }
+        self.directories
+            .insert(name.to_string(), index.directories.into_keys().collect());
+        match self.projects.lock() {
+            Ok(mut projects) => {
+                projects.insert(name.to_string(), index.projects);
+            }
+            Err(_) => error!("{}", Error::PoisonedDaemon),
+        }
+    }
+
+    fn watch(&mut self, directory: &Path) {
+        if self.watched.contains(directory) {
+            return;
+        }
+        let mask = WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::ONLYDIR;
+        match self.inotify.watches().add(directory, mask) {
+            Ok(descriptor) => {
+                self.watches.insert(descriptor, directory.to_path_buf());
+                self.watched.insert(directory.to_path_buf());
+            }
+            Err(err) => warn!("could not watch {:?}: {}", directory, err),
+        }
+    }
+}
+
+/// Bind the socket, replacing a stale socket left behind by a daemon that
+/// is no longer running
+fn bind(socket: &Path) -> Result<UnixListener, Error> {
+    if UnixStream::connect(socket).is_ok() {
+        return Err(Error::DaemonRunning(socket.to_path_buf()));
+    }
+    match fs::remove_file(socket) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(UnixListener::bind(socket)?)
+}
+
+fn serve(listener: UnixListener, projects: Projects) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| respond(stream, &projects));
+        if let Err(err) = result {
+            warn!("could not answer query: {}", err);
+        }
+    }
+}
+
+fn respond(stream: UnixStream, projects: &Projects) -> Result<(), io::Error> {
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match serde_json::from_str(&line) {
+        Ok(Request::Search { workspace }) => match projects.lock() {
+            Ok(projects) => match projects.get(&workspace) {
+                Some(projects) => Response::Projects(projects.clone()),
+                None => Response::Error(
+                    Error::UndefinedWorkspace(workspace).to_string(),
+                ),
+            },
+            Err(_) => Response::Error(Error::PoisonedDaemon.to_string()),
+        },
+        Err(err) => Response::Error(err.to_string()),
+    };
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn query_running_daemon() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.join("daemon.sock");
+        assert_eq!(query(&socket, "default"), None);
+
+        let projects: Projects = Arc::default();
+        projects
+            .lock()
+            .unwrap()
+            .insert("default".to_string(), vec![PathBuf::from("/project")]);
+        let listener = bind(&socket).unwrap();
+        thread::spawn(move || serve(listener, projects));
+
+        assert_eq!(
+            query(&socket, "default"),
+            Some(vec![PathBuf::from("/project")])
+        );
+        assert_eq!(query(&socket, "undefined"), None);
+        assert!(matches!(bind(&socket), Err(Error::DaemonRunning(_))));
+    }
+
+    #[test]
+    fn query_unresponsive_daemon() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.join("daemon.sock");
+        // Connecting succeeds while nothing ever accepts or answers
+        let _listener = bind(&socket).unwrap();
+        assert_eq!(query(&socket, "default"), None);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{env::VarError, path::PathBuf, sync::PoisonError};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
@@ -16,18 +17,52 @@ pub enum Error {
     ),
     #[error("Expected value or default value for argument: {0}")]
     UnhandledMissingArgument(String),
+    #[error("Invalid project marker '{0}': {1}")]
+    InvalidMarker(String, globset::Error),
+    #[error("Invalid exclude glob '{0}': {1}")]
+    InvalidExclude(String, globset::Error),
+    #[error("Invalid style '{0}'; Expected a comma separated list of attributes and colors such as 'bold,fg:green'")]
+    InvalidStyle(String),
+    #[error(
+        "Invalid key binding '{0}'; Expected a control key such as 'ctrl-g'"
+    )]
+    InvalidKeyBinding(String),
+    #[error(transparent)]
+    ThreadPoolError(#[from] rayon::ThreadPoolBuildError),
     #[error(transparent)]
     TomlDeserializeError(#[from] toml::de::Error),
     #[error(transparent)]
     TomlSerializeError(#[from] toml::ser::Error),
+    #[error(transparent)]
+    TomlEditError(#[from] toml_edit::TomlError),
+    #[error(
+        "Expected {0} in the config to be a table or an array as documented"
+    )]
+    MalformedConfig(String),
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
     #[error("The following workspace is undefined: {0}")]
     UndefinedWorkspace(String),
-    #[error("The following workspace is not a toml array: {0}")]
-    InvalidWorkspace(String),
     #[error("The workspace '{0}' already contain '{1}'")]
     DuplicateDirectory(String, PathBuf),
+    #[error("{1:?} overlaps {2:?} of the workspace '{0}' and would find its projects twice; Pass --force to add it anyway")]
+    NestedDirectory(String, PathBuf, PathBuf),
+    #[error("No project matches the query: {0}")]
+    NoMatch(String),
     #[error("Current path is not within a project directory")]
     NotInWorkspace(PathBuf),
     #[error("All directories must be absolute within a workspace; Found {1:?} in {0}")]
     RelativeDirectoryError(String, PathBuf),
+    #[error("The min_depth of {1} in {0} is greater than its max_depth of {2}, which leaves nothing to find")]
+    InvalidDepth(String, usize, usize),
+    #[error("A daemon is already listening on {0:?}")]
+    DaemonRunning(PathBuf),
+    #[error("{0:?} was written for config schema {1} but this binary only supports up to schema {2}; Upgrade project-finder to use it")]
+    NewerConfig(PathBuf, u32, u32),
+    #[error("The schema version in {0:?} must be a non-negative integer within a [metadata] table")]
+    InvalidSchema(PathBuf),
+    #[error("Timed out after {1:?} waiting for another invocation to finish writing {0:?}")]
+    Locked(PathBuf, std::time::Duration),
+    #[error("The daemon state was poisoned by a panicking thread")]
+    PoisonedDaemon,
 }
@@ -0,0 +1,155 @@
+//! Projects chosen in the picker, used to rank the projects visited often and
+//! lately above the rest. Every visit counts for less as it ages, halving
+//! after [`HALF_LIFE`], so the score is a frequency decayed by recency.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::error::Error;
+
+const HALF_LIFE: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+/// Projects whose frecency decayed below this are forgotten
+const MIN_FRECENCY: f64 = 0.01;
+/// Bonus added to a fuzzy score per unit of the natural logarithm of one plus
+/// the frecency, about a well placed character's worth
+const FRECENCY_WEIGHT: f64 = 16.0;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    visits: HashMap<PathBuf, Visits>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Visits {
+    /// Visits decayed up to the last one
+    frecency: f64,
+    last: SystemTime,
+}
+
+impl History {
+    pub fn file(data_dir: &Path) -> PathBuf {
+        data_dir.join("history.json")
+    }
+
+    /// Load the history, treating a missing or unreadable history as empty
+    pub fn load(data_dir: &Path) -> Self {
+        let file = Self::file(data_dir);
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!("no history found at {:?}", file);
+                return Self::default();
+            }
+            Err(err) => {
+                warn!("could not read history {:?}: {}", file, err);
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&content).unwrap_or_else(|err| {
+            warn!("discarding corrupt history {:?}: {}", file, err);
+            Self::default()
+        })
+    }
+
+    pub fn store(&self, data_dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(data_dir)?;
+        fs::write(Self::file(data_dir), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn clear(data_dir: &Path) -> Result<(), Error> {
+        match fs::remove_file(Self::file(data_dir)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(err.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a visit of a project, forgetting projects not visited for long
+    pub fn record(&mut self, path: PathBuf, now: SystemTime) {
+        let frecency = self.frecency(&path, now) + 1.0;
+        self.visits.insert(
+            path,
+            Visits {
+                frecency,
+                last: now,
+            },
+        );
+        self.visits.retain(|_, visits| {
+            visits.frecency * decay(visits.last, now) >= MIN_FRECENCY
+        });
+    }
+
+    pub fn frecency(&self, path: &Path, now: SystemTime) -> f64 {
+        self.visits
+            .get(path)
+            .map_or(0.0, |visits| visits.frecency * decay(visits.last, now))
+    }
+
+    /// Bonus to add to the fuzzy score of a project, growing slower the more
+    /// often it is visited so a frecent project does not bury better matches
+    pub fn bonus(&self, path: &Path, now: SystemTime) -> i64 {
+        (FRECENCY_WEIGHT * self.frecency(path, now).ln_1p()).round() as i64
+    }
+}
+
+/// How much a visit at some time still counts
+fn decay(time: SystemTime, now: SystemTime) -> f64 {
+    let age = now.duration_since(time).unwrap_or_default();
+    0.5_f64.powf(age.as_secs_f64() / HALF_LIFE.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn frecency() {
+        let now = SystemTime::UNIX_EPOCH + HALF_LIFE * 100;
+        let mut history = History::default();
+        history.record("/forgotten".into(), now - HALF_LIFE * 50);
+        history.record("/often".into(), now - HALF_LIFE * 2);
+        history.record("/often".into(), now - HALF_LIFE);
+        history.record("/often".into(), now - HALF_LIFE);
+        history.record("/lately".into(), now);
+
+        assert!(
+            (history.frecency(Path::new("/often"), now) - 1.25).abs() < 1e-9
+        );
+        assert!(
+            (history.frecency(Path::new("/lately"), now) - 1.0).abs() < 1e-9
+        );
+        assert_eq!(history.frecency(Path::new("/unknown"), now), 0.0);
+        assert!(!history.visits.contains_key(Path::new("/forgotten")));
+        assert!(
+            history.bonus(Path::new("/often"), now)
+                > history.bonus(Path::new("/lately"), now)
+        );
+        assert_eq!(history.bonus(Path::new("/unknown"), now), 0);
+    }
+
+    #[test]
+    fn store_load_and_clear() {
+        let temp_dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        let mut history = History::load(&temp_dir);
+        history.record("/project".into(), now);
+        history.store(&temp_dir).unwrap();
+
+        let loaded = History::load(&temp_dir);
+        assert!(loaded.frecency(Path::new("/project"), now) > 0.0);
+
+        History::clear(&temp_dir).unwrap();
+        History::clear(&temp_dir).unwrap();
+        assert!(History::load(&temp_dir).visits.is_empty());
+    }
+}
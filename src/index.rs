@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::error::Error;
+
+/// Projects of a workspace together with every directory visited to find
+/// them, so a later search only has to rescan directories that changed
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    /// The workspace definition the index was built from; the index is
+    /// discarded once it changes
+    pub settings: String,
+    pub projects: Vec<PathBuf>,
+    pub directories: HashMap<PathBuf, Entry>,
+    /// Paths left out of the last search as they could not be read, which
+    /// are tried again by the next one
+    #[serde(skip)]
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub modified: SystemTime,
+    pub project: bool,
+    /// Subdirectories left to search after ignore rules were applied
+    pub children: Vec<PathBuf>,
+    /// Symlinks in the directory whose target is missing, reported again
+    /// from the index for as long as they stay broken
+    #[serde(default)]
+    pub broken: Vec<PathBuf>,
+    /// When each ignore file of the directory was last modified, `None` for
+    /// those missing, as editing one in place changes which subdirectories
+    /// are left to search without modifying the directory itself
+    #[serde(default)]
+    pub ignores: Vec<Option<SystemTime>>,
+    /// Position of the workspace directory the directory was searched from,
+    /// as each applies settings of its own
+    #[serde(default)]
+    pub root: usize,
+}
+
+/// A path the search could not read and left out
+#[derive(Debug)]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl Index {
+    /// The file the index of a workspace is stored in. Bytes of the name
+    /// other than ASCII letters, digits, `-` and `_` are percent-encoded, so
+    /// a name like `a/b` or `..` cannot lead out of the cache directory.
+    pub fn file(cache_dir: &Path, workspace: &str) -> PathBuf {
+        let mut name = String::new();
+        for byte in workspace.bytes() {
+            if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+                name.push(char::from(byte));
+            } else {
+                name.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        cache_dir.join(format!("{}.json", name))
+    }
+
+    /// Load the index of a workspace, treating a missing or unreadable
+    /// index as absent
+    pub fn load(cache_dir: &Path, workspace: &str) -> Option<Self> {
+        let file = Self::file(cache_dir, workspace);
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!("no index found at {:?}", file);
+                return None;
+            }
+            Err(err) => {
+                warn!("could not read index {:?}: {}", file, err);
+                return None;
+            }
+        };
+        match serde_json::from_str(&content) {
+            Ok(index) => Some(index),
+            Err(err) => {
+                warn!("discarding corrupt index {:?}: {}", file, err);
+                None
+            }
+        }
+    }
+
+    /// Store the index through a temporary file renamed over the previous
+    /// one, so a process exiting halfway through leaves the previous index
+    /// rather than a truncated one
+    pub fn store(
+        &self,
+        cache_dir: &Path,
+        workspace: &str,
+    ) -> Result<(), Error> {
+        static STORES: AtomicUsize = AtomicUsize::new(0);
+        fs::create_dir_all(cache_dir)?;
+        let file = Self::file(cache_dir, workspace);
+        // Named after the process and the store, so writers never share one
+        let temp = file.with_extension(format!(
+            "{}.{}.tmp",
+            process::id(),
+            STORES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = fs::write(&temp, serde_json::to_string(self)?)
+            .and_then(|()| fs::rename(&temp, &file));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        Ok(written?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn store_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_dir = temp_dir.join("cache");
+        assert!(Index::load(&cache_dir, "default").is_none());
+
+        let index = Index {
+            settings: "settings".to_string(),
+            projects: vec![PathBuf::from("/project")],
+            directories: HashMap::from([(
+                PathBuf::from("/project"),
+                Entry {
+                    modified: SystemTime::UNIX_EPOCH,
+                    project: true,
+                    children: Vec::new(),
+                    broken: Vec::new(),
+                    ignores: Vec::new(),
+                    root: 0,
+                },
+            )]),
+            skipped: Vec::new(),
+        };
+        index.store(&cache_dir, "default").unwrap();
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+        let loaded = Index::load(&cache_dir, "default").unwrap();
+        assert_eq!(loaded.settings, index.settings);
+        assert_eq!(loaded.projects, index.projects);
+        assert_eq!(loaded.directories.len(), 1);
+
+        fs::write(Index::file(&cache_dir, "default"), "{").unwrap();
+        assert!(Index::load(&cache_dir, "default").is_none());
+    }
+
+    #[test]
+    fn file_stays_in_cache_dir() {
+        let cache_dir = Path::new("/cache");
+        assert_eq!(
+            Index::file(cache_dir, "my-work_2"),
+            Path::new("/cache/my-work_2.json")
+        );
+        assert_eq!(
+            Index::file(cache_dir, "a/b"),
+            Path::new("/cache/a%2Fb.json")
+        );
+        assert_eq!(
+            Index::file(cache_dir, ".."),
+            Path::new("/cache/%2E%2E.json")
+        );
+    }
+}
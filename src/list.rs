@@ -0,0 +1,235 @@
+//! Output of the `list` command, describing workspaces for people as a table
+//! and for scripts as JSON or newline delimited JSON.
+
+use std::{io::Write, path::PathBuf, str::FromStr};
+
+use serde::Serialize;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    /// One JSON object per workspace and line
+    Ndjson,
+}
+
+impl Format {
+    pub const NAMES: [&'static str; 3] = ["table", "json", "ndjson"];
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            name => Err(Error::UnhandledAction(name.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Workspace {
+    pub name: String,
+    pub directories: Vec<Directory>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Directory {
+    pub path: PathBuf,
+    /// Projects found within the directory, if they were asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projects: Option<Vec<PathBuf>>,
+}
+
+impl Directory {
+    /// Assign projects to the directories they were found in
+    pub fn with_projects(
+        directories: Vec<PathBuf>,
+        projects: Vec<PathBuf>,
+    ) -> Vec<Self> {
+        let mut directories: Vec<Self> = directories
+            .into_iter()
+            .map(|path| Self {
+                path,
+                projects: Some(Vec::new()),
+            })
+            .collect();
+        for project in projects {
+            let directory = directories
+                .iter_mut()
+                .find(|directory| project.starts_with(&directory.path));
+            if let Some(Self {
+                projects: Some(projects),
+                ..
+            }) = directory
+            {
+                projects.push(project);
+            }
+        }
+        directories
+    }
+}
+
+pub fn write<W: Write>(
+    mut out: W,
+    workspaces: &[Workspace],
+    format: Format,
+) -> Result<(), Error> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, workspaces)?;
+            writeln!(out)?;
+        }
+        Format::Ndjson => {
+            for workspace in workspaces {
+                serde_json::to_writer(&mut out, workspace)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Table => write!(out, "{}", table(workspaces))?,
+    }
+    Ok(())
+}
+
+/// A row per directory, or per project when projects were asked for, with
+/// columns padded to their widest cell
+fn table(workspaces: &[Workspace]) -> String {
+    let with_projects = workspaces
+        .iter()
+        .flat_map(|workspace| &workspace.directories)
+        .any(|directory| directory.projects.is_some());
+    let mut rows: Vec<Vec<String>> =
+        vec![["WORKSPACE", "DIRECTORY", "PROJECT"]
+            .iter()
+            .take(if with_projects { 3 } else { 2 })
+            .map(|header| header.to_string())
+            .collect()];
+    for workspace in workspaces {
+        if workspace.directories.is_empty() {
+            rows.push(vec![workspace.name.clone()]);
+        }
+        for directory in &workspace.directories {
+            let row = vec![
+                workspace.name.clone(),
+                directory.path.to_string_lossy().into_owned(),
+            ];
+            match &directory.projects {
+                Some(projects) if !projects.is_empty() => {
+                    for project in projects {
+                        let mut row = row.clone();
+                        row.push(project.to_string_lossy().into_owned());
+                        rows.push(row);
+                    }
+                }
+                _ => rows.push(row),
+            }
+        }
+    }
+
+    let mut widths: Vec<usize> = Vec::new();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(i) {
+                Some(max) => *max = (*max).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+    let mut table = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        table += cells.join("  ").trim_end();
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspaces(projects: bool) -> Vec<Workspace> {
+        let directories = vec![PathBuf::from("/code"), PathBuf::from("/work")];
+        let directories = if projects {
+            Directory::with_projects(
+                directories,
+                vec![PathBuf::from("/code/api"), PathBuf::from("/code/web")],
+            )
+        } else {
+            directories
+                .into_iter()
+                .map(|path| Directory {
+                    path,
+                    projects: None,
+                })
+                .collect()
+        };
+        vec![
+            Workspace {
+                name: "default".to_string(),
+                directories,
+            },
+            Workspace {
+                name: "empty".to_string(),
+                directories: Vec::new(),
+            },
+        ]
+    }
+
+    fn output(workspaces: &[Workspace], format: Format) -> String {
+        let mut out = Vec::new();
+        write(&mut out, workspaces, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            output(&workspaces(false), Format::Table),
+            "WORKSPACE  DIRECTORY\n\
+             default    /code\n\
+             default    /work\n\
+             empty\n"
+        );
+        assert_eq!(
+            output(&workspaces(true), Format::Table),
+            "WORKSPACE  DIRECTORY  PROJECT\n\
+             default    /code      /code/api\n\
+             default    /code      /code/web\n\
+             default    /work\n\
+             empty\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            output(&workspaces(true), Format::Ndjson),
+            concat!(
+                r#"{"name":"default","directories":["#,
+                r#"{"path":"/code","projects":["/code/api","/code/web"]},"#,
+                r#"{"path":"/work","projects":[]}]}"#,
+                "\n",
+                r#"{"name":"empty","directories":[]}"#,
+                "\n",
+            )
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&output(&workspaces(false), Format::Json))
+                .unwrap();
+        assert_eq!(
+            json[0]["directories"][1],
+            serde_json::json!({"path": "/work"})
+        );
+    }
+}
@@ -1,45 +1,111 @@
 use std::{
+    collections::{BTreeMap, HashSet},
     env, fs,
-    io::{stdin, stdout},
-    ops::Deref,
+    io::{stdout, BufReader, Write},
+    mem,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
     sync::Mutex,
+    thread,
+    time::SystemTime,
 };
 
 use clap::{crate_version, ArgMatches};
 use lazy_static::lazy_static;
+use matcher::Match;
 use serde::{Deserialize, Serialize};
-use termion::{event::Key, raw::IntoRawMode};
-use toml::{to_string, toml};
-use tracing::{error, Level};
+use termion::{event::Key, get_tty, raw::IntoRawMode};
+use toml::toml;
+use toml_edit::{Array, DocumentMut, Item};
+use tracing::{debug, error, info, warn, Level};
 
+mod atomic;
 mod cli;
+mod daemon;
 mod error;
+mod history;
+mod index;
+mod list;
 mod logger;
+mod marker;
+mod matcher;
+mod migrate;
 mod path_utils;
+mod preview;
+mod query;
+mod search;
+mod shell;
 mod tui;
+mod workspace;
 
+use atomic::Lock;
+use daemon::Daemon;
 use error::Error;
-use tui::Window;
+use history::History;
+use index::{Index, Skipped};
+use query::Query;
+use search::Searcher;
+use shell::Shell;
+use tui::{Event, Window};
+use workspace::{Inference, Overrides, Workspace};
 
 lazy_static! {
     static ref CONFIG_FILE: Mutex<PathBuf> = Mutex::new(PathBuf::new());
+    static ref CACHE_DIR: Mutex<PathBuf> = Mutex::new(PathBuf::new());
+    static ref DATA_DIR: Mutex<PathBuf> = Mutex::new(PathBuf::new());
+    /// Paths the last search of each workspace could not read, reported once
+    /// the command is done
+    static ref SKIPPED: Mutex<BTreeMap<String, Vec<Skipped>>> =
+        Mutex::new(BTreeMap::new());
 }
 
-/// TODO: Api version to be separate from crate version
-/// TODO: Respect .gitignore when searching
-/// TODO: Handle trying to add nested workspace directories and manually added nested workspace
-/// directories
 /// TODO: USE THE FUCKING PATH UTILS YOU HAVE PROGRAMMED!!!!
 /// TODO: Add tracing you dumb fuck, you wasted time setting it up alrady
 fn main() {
     let matches = cli::parse();
 
     logger::init(matches.get_one::<Level>("log_level").copied());
-    if let Err(err) = _main(matches) {
-        error!("{}", err);
+    let verbose = matches.get_flag("verbose");
+    let result = _main(matches);
+    report_skipped(verbose);
+    match result {
+        // Not finding anything is an answer rather than a failure, given by
+        // the exit status alone
+        Err(err @ Error::NoMatch(_)) => {
+            debug!("{}", err);
+            std::process::exit(1);
+        }
+        Err(err) => error!("{}", err),
+        Ok(()) => {}
+    }
+}
+
+/// Tell how many paths could not be searched, listing them when verbose
+fn report_skipped(verbose: bool) {
+    let Ok(skipped) = SKIPPED.lock() else {
+        return;
+    };
+    for (name, skipped) in skipped.iter() {
+        if skipped.is_empty() {
+            continue;
+        }
+        let summary = format!(
+            "Skipped {} unreadable {} in the workspace '{}'",
+            skipped.len(),
+            if skipped.len() == 1 { "path" } else { "paths" },
+            name
+        );
+        if !verbose {
+            eprintln!("{}; Pass --verbose to list them", summary);
+            continue;
+        }
+        eprintln!("{}:", summary);
+        for skip in skipped {
+            eprintln!("  {}: {}", skip.path.display(), skip.error);
+        }
     }
 }
+
 fn _main(matches: ArgMatches) -> Result<(), Error> {
     {
         let mut config = CONFIG_FILE.lock()?;
@@ -49,6 +115,16 @@ fn _main(matches: ArgMatches) -> Result<(), Error> {
             )?,
         )?;
     }
+    {
+        let mut cache_dir = CACHE_DIR.lock()?;
+        *cache_dir = path_utils::xdg_dir("XDG_CACHE_HOME", ".cache")?
+            .join("projectfinder");
+    }
+    {
+        let mut data_dir = DATA_DIR.lock()?;
+        *data_dir = path_utils::xdg_dir("XDG_DATA_HOME", ".local/share")?
+            .join("projectfinder");
+    }
 
     match matches.subcommand() {
         Some(("init", _)) => init(),
@@ -65,12 +141,8 @@ fn _main(matches: ArgMatches) -> Result<(), Error> {
                 )?;
                 remove_workspace(name)
             }
-            Some((name, _)) => {
-                return Err(Error::UnhandledAction(name.to_string()))
-            }
-            None => {
-                return Err(Error::UnhandledAction("workspace".to_string()))
-            }
+            Some((name, _)) => Err(Error::UnhandledAction(name.to_string())),
+            None => Err(Error::UnhandledAction("workspace".to_string())),
         },
         Some(("directory", command)) => match command.subcommand() {
             Some(("add", command)) => {
@@ -84,7 +156,11 @@ fn _main(matches: ArgMatches) -> Result<(), Error> {
                         "project_dir".to_string(),
                     ))?;
                 let project = fs::canonicalize(project)?;
-                add_workspace_directory(name, project)
+                add_workspace_directory(
+                    name,
+                    project,
+                    command.get_flag("force"),
+                )
             }
             Some(("remove", command)) => {
                 let name = command.get_one::<String>("name").cloned().ok_or(
@@ -99,149 +175,616 @@ fn _main(matches: ArgMatches) -> Result<(), Error> {
                 remove_workspace_directory(name, project)
             }
 
-            Some((name, _)) => {
-                return Err(Error::UnhandledAction(name.to_string()))
-            }
-            None => {
-                return Err(Error::UnhandledAction("directory".to_string()))
-            }
+            Some((name, _)) => Err(Error::UnhandledAction(name.to_string())),
+            None => Err(Error::UnhandledAction("directory".to_string())),
         },
         Some(("search", command)) => {
-            let name = command.get_one::<String>("name");
-            let name = match name {
-                Some(name) => name.clone(),
-                None => find_current_workspace()?,
+            let filter_query = command.get_one::<String>("filter");
+            let names = match command.get_one::<String>("name") {
+                Some(name) => vec![name.clone()],
+                None => {
+                    // Filtering is meant for scripts, which cannot be asked
+                    let inference =
+                        match inference(command.get_one("infer").copied())? {
+                            Inference::Prompt if filter_query.is_some() => {
+                                Inference::Specific
+                            }
+                            inference => inference,
+                        };
+                    find_current_workspaces(inference)?
+                }
             };
-
-            let directories = search_workspace(name)?;
-            fzf(directories)?;
-            Ok(())
+            let threads = command.get_one::<usize>("threads").copied();
+            let rescan = command.get_flag("rescan");
+            let overrides = Overrides {
+                exclude: command
+                    .get_many::<String>("exclude")
+                    .map(|globs| globs.cloned().collect())
+                    .unwrap_or_default(),
+                max_depth: command.get_one::<usize>("max_depth").copied(),
+                min_depth: command.get_one::<usize>("min_depth").copied(),
+            };
+            if let Some(query) = filter_query {
+                let paths = filter(&names, threads, rescan, &overrides, query)?;
+                return print_paths(&paths, command.get_flag("print0"));
+            }
+            let query = command
+                .get_many::<String>("query")
+                .map(|words| words.cloned().collect::<Vec<String>>().join(" "))
+                .unwrap_or_default();
+            let selected = search(names, threads, rescan, &overrides, &query)?;
+            choose(selected, command.get_one::<PathBuf>("cd_file"))
         }
-        Some((name, _)) => {
-            return Err(Error::UnhandledAction(name.to_string()))
+        Some(("index", command)) => match command.subcommand() {
+            Some(("rebuild", command)) => {
+                let name = command.get_one::<String>("name").cloned();
+                let threads = command.get_one::<usize>("threads").copied();
+                rebuild_index(name, threads)
+            }
+            Some((name, _)) => Err(Error::UnhandledAction(name.to_string())),
+            None => Err(Error::UnhandledAction("index".to_string())),
+        },
+        Some(("list", command)) => {
+            let name = command.get_one::<String>("name").cloned();
+            let threads = command.get_one::<usize>("threads").copied();
+            let format: list::Format = command
+                .get_one::<String>("format")
+                .ok_or(Error::UnhandledMissingArgument("format".to_string()))?
+                .parse()?;
+            let workspaces =
+                list_workspaces(name, command.get_flag("projects"), threads)?;
+            list::write(stdout().lock(), &workspaces, format)
         }
-        None => {
-            let name = find_current_workspace()?;
-            let directories = search_workspace(name)?;
-            fzf(directories)?;
+        Some(("history", command)) => match command.subcommand() {
+            Some(("clear", _)) => History::clear(&DATA_DIR.lock()?),
+            Some((name, _)) => Err(Error::UnhandledAction(name.to_string())),
+            None => Err(Error::UnhandledAction("history".to_string())),
+        },
+        Some(("daemon", command)) => {
+            let threads = command.get_one::<usize>("threads").copied();
+            daemon(threads)
+        }
+        Some(("shell-init", command)) => {
+            let shell: Shell = command
+                .get_one::<String>("shell")
+                .ok_or(Error::UnhandledMissingArgument("shell".to_string()))?
+                .parse()?;
+            let cmd = command
+                .get_one::<String>("cmd")
+                .ok_or(Error::UnhandledMissingArgument("cmd".to_string()))?;
+            let bind = command
+                .get_one::<String>("bind")
+                .map(|bind| shell::parse_binding(bind))
+                .transpose()?;
+            print!("{}", shell.init(cmd, bind));
             Ok(())
         }
+        Some((name, _)) => Err(Error::UnhandledAction(name.to_string())),
+        None => {
+            let names = find_current_workspaces(inference(None)?)?;
+            let query = matches
+                .get_many::<String>("query")
+                .map(|words| words.cloned().collect::<Vec<String>>().join(" "))
+                .unwrap_or_default();
+            let selected =
+                search(names, None, false, &Overrides::default(), &query)?;
+            choose(selected, matches.get_one::<PathBuf>("cd_file"))
+        }
     }
 }
 
 fn init() -> Result<(), Error> {
     let version = crate_version!();
+    let schema = i64::from(migrate::SCHEMA);
     let content = toml! {
         [metadata]
         version = version
+        schema = schema
         [workspaces]
     };
     {
-        fs::write(CONFIG_FILE.lock()?.deref(), content.to_string())?;
+        let config_file = CONFIG_FILE.lock()?;
+        let _lock = Lock::acquire(&config_file, atomic::LOCK_TIMEOUT)?;
+        atomic::write(&config_file, content.to_string())?;
     }
 
     Ok(())
 }
 
-fn find_current_workspace() -> Result<String, Error> {
-    let config_file = CONFIG_FILE.lock()?;
-    let content = fs::read_to_string(config_file.deref())?;
-    let config: Config = toml::from_str(&content)?;
+/// How to infer the workspace to search, given by `--infer` or else the
+/// settings
+fn inference(arg: Option<Inference>) -> Result<Inference, Error> {
+    Ok(arg.or(read_settings()?.infer).unwrap_or_default())
+}
+
+/// The workspaces to search from the current directory, which is none when
+/// the user is asked and picks none
+fn find_current_workspaces(inference: Inference) -> Result<Vec<String>, Error> {
+    let config = read_config(&CONFIG_FILE.lock()?)?;
 
     let current_path = env::current_dir()?;
-    let ancestors = current_path.ancestors();
-    for (name, workspace) in config.workspaces {
-        let workspace = workspace
-            .as_array()
-            .ok_or(Error::InvalidWorkspace(name.clone()))?;
-        for project in workspace {
-            let project = PathBuf::from(
-                project
-                    .as_str()
-                    .ok_or(Error::InvalidWorkspace(name.clone()))?,
-            );
-            for ancestor in ancestors {
-                if ancestor == project {
-                    return Ok(name);
+    let candidates = workspace::infer(&config.workspaces, &current_path);
+    debug!("workspaces containing {:?}: {:?}", current_path, candidates);
+    let names: Vec<String> = match (inference, candidates.as_slice()) {
+        (_, []) => return Err(Error::NotInWorkspace(current_path)),
+        (Inference::Union, candidates) => {
+            candidates.iter().map(|name| name.to_string()).collect()
+        }
+        (Inference::Prompt, [_, _, ..]) => {
+            let tty = get_tty()?;
+            let options: Vec<&str> =
+                candidates.iter().map(|name| name.as_str()).collect();
+            tui::pick(
+                BufReader::new(tty.try_clone()?),
+                tty,
+                "Workspace",
+                &options,
+            )?
+            .map(|picked| options[picked].to_string())
+            .into_iter()
+            .collect()
+        }
+        (_, [name, ..]) => vec![name.to_string()],
+    };
+    Ok(names)
+}
+
+/// Hand the chosen project to the shell, which reads it from the cd file
+/// when one is given or from stdout otherwise
+fn choose(
+    selected: Option<PathBuf>,
+    cd_file: Option<&PathBuf>,
+) -> Result<(), Error> {
+    let selected = match selected {
+        Some(selected) => selected,
+        None => return Ok(()),
+    };
+    info!("chose {:?}", selected);
+    match cd_file {
+        Some(cd_file) => fs::write(cd_file, selected.as_os_str().as_bytes())?,
+        None => {
+            let mut stdout = stdout().lock();
+            stdout.write_all(selected.as_os_str().as_bytes())?;
+            stdout.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// Open the picker for a workspace and return the chosen project. A
+/// previously indexed workspace is shown right away while the index is
+/// refreshed in the background, unless settings are overridden for this
+/// search which the index knows nothing of.
+fn search(
+    names: Vec<String>,
+    threads: Option<usize>,
+    rescan: bool,
+    overrides: &Overrides,
+    query: &str,
+) -> Result<Option<PathBuf>, Error> {
+    if names.is_empty() {
+        return Ok(None);
+    }
+    if !overrides.is_empty() {
+        let projects = search_workspaces(&names, threads, rescan, overrides)?;
+        return pick(projects, None, query);
+    }
+    if !rescan {
+        let socket = daemon_socket()?;
+        let projects: Option<Vec<Vec<PathBuf>>> = names
+            .iter()
+            .map(|name| daemon::query(&socket, name))
+            .collect();
+        if let Some(projects) = projects {
+            return pick(union(projects), None, query);
+        }
+    }
+    let cache_dir = CACHE_DIR.lock()?.clone();
+    let cached: Option<Vec<Vec<PathBuf>>> = names
+        .iter()
+        .map(|name| {
+            Index::load(&cache_dir, name)
+                .filter(|_| !rescan)
+                .map(|index| index.projects)
+        })
+        .collect();
+    match cached {
+        Some(projects) => pick(
+            union(projects),
+            Some(Box::new(move || {
+                search_workspaces(&names, threads, false, &Overrides::default())
+            })),
+            query,
+        ),
+        None => pick(
+            search_workspaces(&names, threads, rescan, overrides)?,
+            None,
+            query,
+        ),
+    }
+}
+
+/// The projects of several workspaces, listing a project within more than
+/// one of them only once
+fn union(projects: Vec<Vec<PathBuf>>) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    projects
+        .into_iter()
+        .flatten()
+        .filter(|project| seen.insert(project.clone()))
+        .collect()
+}
+
+/// Choose a project, jumping straight to the clear best match of a query and
+/// opening the picker with the query typed in when the match is ambiguous.
+/// The choice is recorded in the history.
+fn pick(
+    paths: Vec<PathBuf>,
+    refresh: Option<Refresh>,
+    query: &str,
+) -> Result<Option<PathBuf>, Error> {
+    let data_dir = DATA_DIR.lock()?.clone();
+    let mut history = History::load(&data_dir);
+    let parsed = Query::parse(query);
+    let now = SystemTime::now();
+    // Cached paths may be gone by now, in which case the picker is opened to
+    // show the refreshed paths instead
+    let best = (!query.trim().is_empty())
+        .then(|| {
+            query::clear_best(&paths, |path| {
+                score(&parsed, &history, now, path)
+            })
+        })
+        .flatten()
+        .filter(|best| best.is_dir())
+        .cloned();
+    let selected = match best {
+        Some(best) => {
+            info!("jumping to the best match {:?}", best);
+            Some(best)
+        }
+        None => fzf(paths, refresh, query, &history)?,
+    };
+    if let Some(selected) = &selected {
+        history.record(selected.clone(), SystemTime::now());
+        history.store(&data_dir)?;
+    }
+    Ok(selected)
+}
+
+/// Rank the projects of a workspace against a query the way the picker does,
+/// failing when none of them match
+fn filter(
+    names: &[String],
+    threads: Option<usize>,
+    rescan: bool,
+    overrides: &Overrides,
+    query: &str,
+) -> Result<Vec<PathBuf>, Error> {
+    let socket = daemon_socket()?;
+    let projects = names
+        .iter()
+        .map(|name| {
+            if !rescan && overrides.is_empty() {
+                if let Some(projects) = daemon::query(&socket, name) {
+                    return Ok(projects);
                 }
             }
+            search_workspace(name.clone(), threads, rescan, overrides)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let projects = union(projects);
+    let history = History::load(&DATA_DIR.lock()?);
+    let now = SystemTime::now();
+    let parsed = Query::parse(query);
+    let ranked =
+        query::rank(&projects, |path| score(&parsed, &history, now, path));
+    if ranked.is_empty() {
+        return Err(Error::NoMatch(query.to_string()));
+    }
+    Ok(ranked.into_iter().map(|(path, _)| path).collect())
+}
+
+fn print_paths(paths: &[PathBuf], print0: bool) -> Result<(), Error> {
+    let separator: &[u8] = if print0 { b"\0" } else { b"\n" };
+    let mut stdout = stdout().lock();
+    for path in paths {
+        stdout.write_all(path.as_os_str().as_bytes())?;
+        stdout.write_all(separator)?;
+    }
+    Ok(())
+}
+
+/// Score a path against a query, favouring frecently chosen projects
+fn score(
+    query: &Query,
+    history: &History,
+    now: SystemTime,
+    path: &Path,
+) -> Option<Match> {
+    let mut found = query.find(&path.to_string_lossy())?;
+    found.score += history.bonus(path, now);
+    Some(found)
+}
+
+fn rebuild_index(
+    name: Option<String>,
+    threads: Option<usize>,
+) -> Result<(), Error> {
+    let names = match name {
+        Some(name) => vec![name],
+        None => workspace_names()?,
+    };
+    for name in names {
+        info!("rebuilding index of {}", name);
+        index_workspace(&name, threads, true)?;
+    }
+    Ok(())
+}
+
+fn daemon(threads: Option<usize>) -> Result<(), Error> {
+    let config_file = CONFIG_FILE.lock()?.clone();
+    Daemon::new(
+        config_file,
+        |name| index_workspace(name, threads, false),
+        workspace_names,
+    )?
+    .run(&daemon_socket()?)
+}
+
+/// Socket the daemon listens on, placed in the runtime directory when there
+/// is one
+fn daemon_socket() -> Result<PathBuf, Error> {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) if !runtime_dir.is_empty() => {
+            Ok(PathBuf::from(runtime_dir).join("projectfinder.sock"))
         }
+        _ => Ok(CACHE_DIR.lock()?.join("daemon.sock")),
     }
-    Err(Error::NotInWorkspace(current_path))
 }
 
-fn search_workspace(name: String) -> Result<Vec<PathBuf>, Error> {
-    let config_file = CONFIG_FILE.lock()?;
-    let content = fs::read_to_string(config_file.deref())?;
-    let config: Config = toml::from_str(&content)?;
-    let workspace = config
-        .workspaces
-        .get(&name)
-        .ok_or(Error::UndefinedWorkspace(name.clone()))?
-        .as_array()
-        .ok_or(Error::InvalidWorkspace(name.clone()))?;
-
-    let mut directories: Vec<PathBuf> = Vec::new();
-    println!("{:?}", workspace);
-    for directory in workspace {
-        let directory: PathBuf = directory
-            .as_str()
-            .ok_or(Error::InvalidWorkspace(name.clone()))?
-            .into();
-        if !directory.is_absolute() {
-            return Err(Error::RelativeDirectoryError(
+/// Read the config, upgrading it first if it was written for an older schema,
+/// and validate every workspace in it
+fn read_config(config_file: &Path) -> Result<Config, Error> {
+    let content = fs::read_to_string(config_file)?;
+    let config = if migrate::is_current(config_file, &content)? {
+        parse_config(&content)?
+    } else {
+        let _lock = Lock::acquire(config_file, atomic::LOCK_TIMEOUT)?;
+        load_config(config_file)?.0
+    };
+    for (name, workspace) in &config.workspaces {
+        workspace.validate(name)?;
+    }
+    Ok(config)
+}
+
+/// Read and upgrade the config along with its content, which must be done
+/// holding the config lock as an upgrade writes the config
+fn load_config(config_file: &Path) -> Result<(Config, String), Error> {
+    let mut content = fs::read_to_string(config_file)?;
+    if let Some(upgraded) = migrate::upgrade(config_file, &content)? {
+        atomic::write(config_file, &upgraded)?;
+        content = upgraded;
+    }
+    Ok((parse_config(&content)?, content))
+}
+
+/// Parse the config without validating its workspaces, which is left to
+/// [`read_config`] so [`edit_config`] can still fix or remove a broken one
+fn parse_config(content: &str) -> Result<Config, Error> {
+    Ok(toml::from_str(content)?)
+}
+
+fn read_settings() -> Result<Settings, Error> {
+    let config = read_config(&CONFIG_FILE.lock()?)?;
+    Ok(config.settings.unwrap_or_default())
+}
+
+/// Describe a workspace or every workspace, along with the projects in each
+/// directory if asked for
+fn list_workspaces(
+    name: Option<String>,
+    projects: bool,
+    threads: Option<usize>,
+) -> Result<Vec<list::Workspace>, Error> {
+    let config = read_config(&CONFIG_FILE.lock()?)?;
+    let names = match name {
+        Some(name) => vec![name],
+        None => config.workspaces.keys().cloned().collect(),
+    };
+    let mut workspaces = Vec::new();
+    for name in names {
+        let workspace = config
+            .workspaces
+            .get(&name)
+            .ok_or(Error::UndefinedWorkspace(name.clone()))?;
+        let directories = workspace
+            .directories
+            .iter()
+            .map(|directory| directory.path.clone())
+            .collect();
+        let directories = if projects {
+            let projects = search_workspace(
                 name.clone(),
-                directory.clone(),
-            ));
-        }
-        directories = search_directory(directory, directories)?;
+                threads,
+                false,
+                &Overrides::default(),
+            )?;
+            list::Directory::with_projects(directories, projects)
+        } else {
+            directories
+                .into_iter()
+                .map(|path| list::Directory {
+                    path,
+                    projects: None,
+                })
+                .collect()
+        };
+        workspaces.push(list::Workspace { name, directories });
     }
-    return Ok(directories);
+    Ok(workspaces)
 }
 
-fn search_directory(
-    directory: PathBuf,
-    mut directories: Vec<PathBuf>,
+fn workspace_names() -> Result<Vec<String>, Error> {
+    let config = read_config(&CONFIG_FILE.lock()?)?;
+    Ok(config.workspaces.keys().cloned().collect())
+}
+
+/// Search a workspace, through its index unless settings are overridden for
+/// this search alone, in which case the index is left as it is
+fn search_workspace(
+    name: String,
+    threads: Option<usize>,
+    rescan: bool,
+    overrides: &Overrides,
 ) -> Result<Vec<PathBuf>, Error> {
-    let git_dir = directory.join(".git");
-    if git_dir.try_exists()? && git_dir.is_dir() {
-        directories.push(directory);
-        return Ok(directories);
-    }
-    for entry in fs::read_dir(&directory)? {
-        let entry = entry?.path();
-        if entry.is_dir() {
-            directories = search_directory(entry, directories)?;
-        }
+    if overrides.is_empty() {
+        return Ok(index_workspace(&name, threads, rescan)?.projects);
     }
+    let (workspace, searcher) = workspace_searcher(&name, threads, overrides)?;
+    let mut index = searcher.index(workspace.roots()?, None)?;
+    record_skipped(&name, &mut index);
+    Ok(index.projects)
+}
+
+fn search_workspaces(
+    names: &[String],
+    threads: Option<usize>,
+    rescan: bool,
+    overrides: &Overrides,
+) -> Result<Vec<PathBuf>, Error> {
+    let projects = names
+        .iter()
+        .map(|name| search_workspace(name.clone(), threads, rescan, overrides))
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(union(projects))
+}
+
+/// Search a workspace and update its index. Unless a rescan is forced, only
+/// directories modified since the workspace was last indexed are read again.
+fn index_workspace(
+    name: &str,
+    threads: Option<usize>,
+    rescan: bool,
+) -> Result<Index, Error> {
+    let (workspace, searcher) =
+        workspace_searcher(name, threads, &Overrides::default())?;
 
-    Ok(directories)
+    let cache_dir = CACHE_DIR.lock()?.clone();
+    let settings = serde_json::to_string(&workspace)?;
+    let previous = Index::load(&cache_dir, name)
+        .filter(|index| !rescan && index.settings == settings);
+    let mut index = searcher.index(workspace.roots()?, previous.as_ref())?;
+    index.settings = settings;
+    index.store(&cache_dir, name)?;
+    record_skipped(name, &mut index);
+    Ok(index)
 }
 
-fn fzf(paths: Vec<PathBuf>) -> Result<(), Error> {
+/// A workspace with the settings of a search applied, along with the
+/// searcher to search it with
+fn workspace_searcher(
+    name: &str,
+    threads: Option<usize>,
+    overrides: &Overrides,
+) -> Result<(Workspace, Searcher), Error> {
+    let config = read_config(&CONFIG_FILE.lock()?)?;
+    let mut workspace = config
+        .workspaces
+        .get(name)
+        .cloned()
+        .ok_or(Error::UndefinedWorkspace(name.to_string()))?;
+    workspace.apply(overrides);
+    let threads = threads
+        .or(config.settings.and_then(|settings| settings.threads))
+        .unwrap_or(0);
+    let searcher = workspace.searcher(threads)?;
+    debug!("searching workspace {}: {:?}", name, workspace);
+    Ok((workspace, searcher))
+}
+
+fn record_skipped(name: &str, index: &mut Index) {
+    if let Ok(mut skipped) = SKIPPED.lock() {
+        skipped.insert(name.to_string(), mem::take(&mut index.skipped));
+    }
+}
+
+/// Replaces the paths shown by [`fzf`] once it finishes
+type Refresh = Box<dyn FnOnce() -> Result<Vec<PathBuf>, Error> + Send>;
+
+/// Let the user pick one of the paths on the terminal, leaving stdout free
+/// for the chosen path. Returns `None` when the user quits without choosing.
+fn fzf(
+    paths: Vec<PathBuf>,
+    refresh: Option<Refresh>,
+    input: &str,
+    history: &History,
+) -> Result<Option<PathBuf>, Error> {
+    let settings = read_settings()?;
+    let tty = get_tty()?;
     let mut window =
-        Window::init(stdin(), stdout().lock().into_raw_mode()?, paths)?;
+        Window::init(tty.try_clone()?, tty.into_raw_mode()?, paths)?;
     window.register_help(Key::Ctrl('c'), "Quit")?;
     window.register_help(Key::Char('\n'), "Choose")?;
+    window.register_help(Key::Up, "Up")?;
+    window.register_help(Key::Down, "Down")?;
+    window.register_help(Key::Ctrl('t'), "Preview")?;
+    window.set_input(input.to_string());
+    if let Some(highlight) = settings.highlight {
+        window.set_highlight(highlight.parse()?);
+    }
+    if let Some(refresh) = refresh {
+        let sender = window.sender();
+        thread::spawn(move || match refresh() {
+            Ok(paths) => {
+                let _ = sender.send(Ok(Event::Paths(paths)));
+            }
+            Err(err) => warn!("could not refresh projects: {}", err),
+        });
+    }
+    let previews = preview::spawn(settings.preview, window.sender());
+    let mut previewing = None;
+    let now = SystemTime::now();
     loop {
-        let input = window.get_input().to_string();
-        // TODO: Create a proper fzf filter function including sort etc
-        window.filter_paths(|path| path.to_str().unwrap().contains(&input));
+        let query = Query::parse(window.get_input());
+        window.filter_paths(|path| score(&query, history, now, path));
+        if let Some(path) = window
+            .preview_path()
+            .filter(|path| previewing.as_ref() != Some(path))
+        {
+            previewing = Some(path.clone());
+            let _ = previews.send(path);
+        }
         window.draw_paths()?;
         let key = match window.next() {
-            Some(key) => key?,
+            Some(Ok(Event::Key(key))) => key,
+            Some(Ok(Event::Paths(paths))) => {
+                window.set_paths(paths);
+                continue;
+            }
+            Some(Ok(Event::Preview(path, lines))) => {
+                window.set_preview(path, lines);
+                continue;
+            }
+            Some(Err(err)) => return Err(err),
             None => break,
         };
         match key {
             Key::Ctrl('c') => break,
-            Key::Char('\n') => {
-                let selected = match window.get_selected() {
-                    Some(selected) => selected,
-                    None => continue,
-                };
-                std::env::set_current_dir(selected)?;
-                break;
+            Key::Char('\n') => match window.get_selected() {
+                Some(selected) => return Ok(Some(selected)),
+                None => continue,
+            },
+            Key::Up | Key::Ctrl('p') => window.move_selection(1),
+            Key::Down | Key::Ctrl('n') => window.move_selection(-1),
+            Key::PageUp => window.move_selection(window.page_size() as isize),
+            Key::PageDown => {
+                window.move_selection(-(window.page_size() as isize))
             }
+            Key::Ctrl('t') => window.toggle_preview(),
+            Key::Home => window.select_first(),
+            Key::End => window.select_last(),
             Key::Char(ch) => window.push(ch),
             Key::Backspace => {
                 let _ = window.pop();
@@ -249,103 +792,219 @@ fn fzf(paths: Vec<PathBuf>) -> Result<(), Error> {
             _ => continue,
         }
     }
-    Ok(())
+    Ok(None)
 }
 
 fn remove_workspace_directory(
     name: String,
     project: PathBuf,
 ) -> Result<(), Error> {
-    let config_file = CONFIG_FILE.lock()?;
-    let content = fs::read_to_string(config_file.deref())?;
-    let mut config: Config = toml::from_str(&content)?;
-    let workspace = config
-        .workspaces
-        .get_mut(&name)
-        .ok_or(Error::UndefinedWorkspace(name.clone()))?
-        .as_array_mut()
-        .ok_or(Error::InvalidWorkspace(name.clone()))?;
-    for i in 0..workspace.len() {
-        if PathBuf::from(
-            workspace
-                .get(i)
-                .unwrap()
+    edit_config(|_, document| {
+        let directories = workspace_directories_mut(document, &name)?;
+        // Whatever leads the first directory, a line break in a multiline
+        // array, belongs in front of the first remaining one
+        let prefix = directories
+            .get(0)
+            .and_then(|directory| directory.decor().prefix())
+            .cloned();
+        directories.retain(|directory| {
+            let path = directory
                 .as_str()
-                .ok_or(Error::InvalidWorkspace(name.clone()))?,
-        ) == project
-        {
-            workspace.remove(i);
+                .or_else(|| directory.as_inline_table()?.get("path")?.as_str());
+            path.map(Path::new) != Some(project.as_path())
+        });
+        if let (Some(prefix), Some(first)) = (prefix, directories.get_mut(0)) {
+            first.decor_mut().set_prefix(prefix);
         }
-    }
-    fs::write(config_file.deref(), toml::to_string(&config)?)?;
-
-    Ok(())
+        Ok(())
+    })
 }
 
+/// Add a directory to a workspace. A directory within or containing another
+/// directory of the same workspace would have its projects found twice, so
+/// it is refused unless forced, while overlapping another workspace is only
+/// worth a warning.
 fn add_workspace_directory(
     name: String,
     project: PathBuf,
+    force: bool,
 ) -> Result<(), Error> {
-    let config_file = CONFIG_FILE.lock()?;
-    let content = fs::read_to_string(config_file.deref())?;
-    let mut config: Config = toml::from_str(&content)?;
-    let workspace = config
-        .workspaces
-        .get_mut(&name)
-        .ok_or(Error::UndefinedWorkspace(name.clone()))?
-        .as_array_mut()
-        .ok_or(Error::InvalidWorkspace(name.clone()))?;
-    for i in 0..workspace.len() {
-        if PathBuf::from(
-            workspace
-                .get(i)
-                .unwrap()
-                .as_str()
-                .ok_or(Error::InvalidWorkspace(name.clone()))?,
-        ) == project
-        {
-            return Err(Error::DuplicateDirectory(name, project));
+    edit_config(|config, document| {
+        let workspace = config
+            .workspaces
+            .get(&name)
+            .ok_or(Error::UndefinedWorkspace(name.clone()))?;
+        if workspace.contains(&project) {
+            return Err(Error::DuplicateDirectory(
+                name.clone(),
+                project.clone(),
+            ));
         }
-    }
-    workspace.push(toml::Value::String(project.to_string_lossy().to_string()));
-    fs::write(config_file.deref(), toml::to_string(&config)?)?;
+        for (other, workspace) in &config.workspaces {
+            let Some(overlap) = workspace.overlapping(&project) else {
+                continue;
+            };
+            if *other == name && !force {
+                return Err(Error::NestedDirectory(
+                    name.clone(),
+                    project.clone(),
+                    overlap.clone(),
+                ));
+            }
+            warn!(
+                "{:?} overlaps {:?} of the workspace {}",
+                project, overlap, other
+            );
+        }
+        push_directory(
+            workspace_directories_mut(document, &name)?,
+            &project.to_string_lossy(),
+        );
+        Ok(())
+    })
+}
 
-    Ok(())
+/// Append a directory to the directories of a workspace. In an array written
+/// one directory per line it goes on a line of its own, below any comment
+/// following the directory that was last.
+fn push_directory(directories: &mut Array, directory: &str) {
+    let decor = directories
+        .iter()
+        .last()
+        .map(|last| last.decor())
+        .and_then(|decor| {
+            Some((decor.prefix()?.as_str()?, decor.suffix()?.as_str()?))
+        })
+        .filter(|(prefix, _)| prefix.contains('\n'))
+        .map(|(prefix, suffix)| (prefix.to_string(), suffix.to_string()));
+    directories.push(directory);
+    let Some((prefix, suffix)) = decor else {
+        return;
+    };
+    // The line break closing the array moves from the directory that was
+    // last, or from after the trailing comma, to the one appended
+    let trailing = directories.trailing().as_str().unwrap_or_default();
+    let (prefix, trailing) = match trailing.rsplit_once('\n') {
+        Some((comment, closing)) => {
+            let indent = prefix.rsplit('\n').next().unwrap_or_default();
+            (format!("{}\n{}", comment, indent), format!("\n{}", closing))
+        }
+        None => (prefix, trailing.to_string()),
+    };
+    directories.set_trailing(trailing);
+    let last = directories.len() - 1;
+    if let Some(previous) = directories.get_mut(last - 1) {
+        previous.decor_mut().set_suffix("");
+    }
+    if let Some(appended) = directories.get_mut(last) {
+        appended.decor_mut().set_prefix(prefix);
+        appended.decor_mut().set_suffix(suffix);
+    }
 }
 
 fn remove_workspace(name: String) -> Result<(), Error> {
-    let config_file = CONFIG_FILE.lock()?;
-    let content = fs::read_to_string(config_file.deref())?;
-    let mut config: Config = toml::from_str(&content)?;
-    config.workspaces.remove(&name);
-    fs::write(config_file.deref(), toml::to_string(&config)?)?;
-
-    Ok(())
+    edit_config(|_, document| {
+        if let Some(workspaces) = document
+            .get_mut("workspaces")
+            .and_then(Item::as_table_like_mut)
+        {
+            workspaces.remove(&name);
+        }
+        Ok(())
+    })
 }
 
 fn create_workspace(name: String) -> Result<(), Error> {
+    edit_config(|_, document| {
+        document
+            .entry("workspaces")
+            .or_insert_with(toml_edit::table)
+            .as_table_like_mut()
+            .ok_or(Error::MalformedConfig("workspaces".to_string()))?
+            .insert(&name, toml_edit::value(Array::new()));
+        Ok(())
+    })
+}
+
+/// Change the config file in place, so the comments, order and formatting
+/// written by hand survive. The edit is given the parsed config to make
+/// decisions on along with the document to change, without validating it so
+/// a broken workspace can still be removed. The config stays locked from
+/// reading to writing, so concurrent edits wait for each other instead of
+/// losing one.
+fn edit_config<E>(edit: E) -> Result<(), Error>
+where
+    E: FnOnce(&Config, &mut DocumentMut) -> Result<(), Error>,
+{
     let config_file = CONFIG_FILE.lock()?;
-    let content = fs::read_to_string(config_file.deref())?;
-    let mut config: Config = toml::from_str(&content)?;
-    config.workspaces.insert(name, toml::Value::Array(vec![]));
-    fs::write(config_file.deref(), toml::to_string(&config)?)?;
-    Ok(())
+    let _lock = Lock::acquire(&config_file, atomic::LOCK_TIMEOUT)?;
+    let (config, content) = load_config(&config_file)?;
+    let mut document: DocumentMut = content.parse()?;
+    edit(&config, &mut document)?;
+    atomic::write(&config_file, document.to_string())
+}
+
+/// The directories of a workspace within the config document, in either the
+/// array or the table form of a workspace
+fn workspace_directories_mut<'a>(
+    document: &'a mut DocumentMut,
+    name: &str,
+) -> Result<&'a mut Array, Error> {
+    let workspace = document
+        .get_mut("workspaces")
+        .and_then(|workspaces| workspaces.get_mut(name))
+        .ok_or(Error::UndefinedWorkspace(name.to_string()))?;
+    let malformed = || Error::MalformedConfig(format!("workspaces.{}", name));
+    if workspace.is_array() {
+        return workspace.as_array_mut().ok_or_else(malformed);
+    }
+    workspace
+        .as_table_like_mut()
+        .ok_or_else(malformed)?
+        .entry("directories")
+        .or_insert(toml_edit::value(Array::new()))
+        .as_array_mut()
+        .ok_or_else(malformed)
 }
 
 #[derive(Serialize, Deserialize)]
 struct Config {
     metadata: Metadata,
-    workspaces: toml::Table,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    settings: Option<Settings>,
+    workspaces: BTreeMap<String, Workspace>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct Metadata {
+    /// Version of the binary that wrote the config
     version: String,
+    /// Version of the config format, see [`migrate`]
+    #[serde(default)]
+    schema: u32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Settings {
+    /// Default amount of threads used to search, overridden by `--threads`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    threads: Option<usize>,
+    /// Style of the matched characters in the search results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    highlight: Option<String>,
+    /// Command previewing the selected project, where `{}` is replaced by
+    /// its path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preview: Option<String>,
+    /// What to search when the current directory is within several
+    /// workspaces, overridden by `--infer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    infer: Option<Inference>,
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{path::Path, str::FromStr};
+    use std::{ops::Deref, path::Path, str::FromStr};
 
     use super::*;
     use assert_fs::TempDir;
@@ -371,6 +1030,14 @@ mod tests {
                 let mut config = CONFIG_FILE.lock().unwrap();
                 *config = config_file.clone();
             }
+            {
+                let mut cache_dir = CACHE_DIR.lock().unwrap();
+                *cache_dir = temp_dir.join("cache");
+            }
+            {
+                let mut data_dir = DATA_DIR.lock().unwrap();
+                *data_dir = temp_dir.join("data");
+            }
 
             Self {
                 temp_dir,
@@ -417,9 +1084,11 @@ mod tests {
         let config = toml::Table::from_str(&string_config).unwrap();
 
         let version = crate_version!();
+        let schema = i64::from(migrate::SCHEMA);
         let maybe_config = toml! {
             [metadata]
             version = version
+            schema = schema
 
             [workspaces]
         };
@@ -441,7 +1110,7 @@ mod tests {
         assert!(config.workspaces.contains_key(workspace_name));
         assert_eq!(
             config.workspaces.get(workspace_name).unwrap(),
-            &toml::Value::Array(vec![])
+            &Workspace::default()
         );
     }
 
@@ -459,6 +1128,34 @@ mod tests {
         assert!(!config.workspaces.contains_key(&workspace_name));
     }
 
+    #[test]
+    #[serial]
+    fn test_broken_workspace_can_be_removed() {
+        let test_env = TestEnvironment::new().init();
+        let config = fs::read_to_string(&test_env.config_file).unwrap();
+        fs::write(&test_env.config_file, config + "broken = [\"relative\"]\n")
+            .unwrap();
+
+        let search = |name: &str| {
+            search_workspace(
+                name.to_string(),
+                None,
+                false,
+                &Overrides::default(),
+            )
+        };
+        assert!(matches!(
+            search(&test_env.default_workspace),
+            Err(Error::RelativeDirectoryError(_, _))
+        ));
+        remove_workspace("broken".to_string()).unwrap();
+        assert!(search(&test_env.default_workspace).is_ok());
+        assert!(matches!(
+            search("broken"),
+            Err(Error::UndefinedWorkspace(_))
+        ));
+    }
+
     #[test]
     #[serial]
     fn test_add_directory() {
@@ -467,21 +1164,19 @@ mod tests {
         add_workspace_directory(
             test_env.default_workspace.clone(),
             project_dir.clone(),
+            false,
         )
         .unwrap();
         let content = fs::read_to_string(test_env.config_file).unwrap();
         let config: Config = toml::from_str(&content).unwrap();
-        let workspace = config
+        let workspace = &config
             .workspaces
             .get(&test_env.default_workspace)
             .unwrap()
-            .as_array()
-            .unwrap();
+            .directories;
         let mut counter = 0;
         for directory in workspace {
-            if PathBuf::from(directory.as_str().unwrap())
-                == test_env.project_dir
-            {
+            if directory.path == test_env.project_dir {
                 counter += 1;
             }
         }
@@ -504,36 +1199,152 @@ mod tests {
         .unwrap();
         let content = fs::read_to_string(test_env.config_file).unwrap();
         let config: Config = toml::from_str(&content).unwrap();
-        let workspace = config
+        let workspace = &config
             .workspaces
             .get(&test_env.default_workspace)
             .unwrap()
-            .as_array()
-            .unwrap();
+            .directories;
         for directory in workspace {
-            if PathBuf::from(directory.as_str().unwrap())
-                == test_env.project_dir
-            {
+            if directory.path == test_env.project_dir {
                 panic!("Did not delete all instances of directory within workspace");
             }
         }
     }
 
+    #[test]
+    #[serial]
+    fn test_add_nested_directory() {
+        let test_env = TestEnvironment::new().init();
+        let nested = test_env.project_dir.join("nested");
+        assert!(matches!(
+            add_workspace_directory(
+                test_env.default_workspace.clone(),
+                nested.clone(),
+                false,
+            ),
+            Err(Error::NestedDirectory(..))
+        ));
+        add_workspace_directory(
+            test_env.default_workspace.clone(),
+            nested,
+            true,
+        )
+        .unwrap();
+        let config = read_config(&test_env.config_file).unwrap();
+        assert_eq!(
+            config.workspaces[&test_env.default_workspace]
+                .directories
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_edits_preserve_comments() {
+        let test_env = TestEnvironment::new();
+        let config = r#"# Written by hand
+[metadata]
+version = "0.1.0"
+schema = 1
+
+[workspaces]
+# Personal projects
+default = [
+    "/code", # the main one
+]
+work = { directories = ["/work", { path = "/vendor", max_depth = 1 }], max_depth = 2 }
+tools = [
+  "/bin",
+  "/sbin"
+]
+
+[workspaces.old]
+# Kept for reference
+description = "Archived"
+"#;
+        fs::write(&test_env.config_file, config).unwrap();
+
+        add_workspace_directory("default".to_string(), "/other".into(), false)
+            .unwrap();
+        add_workspace_directory("work".to_string(), "/job".into(), false)
+            .unwrap();
+        add_workspace_directory("old".to_string(), "/old".into(), false)
+            .unwrap();
+        add_workspace_directory("tools".to_string(), "/opt".into(), false)
+            .unwrap();
+        remove_workspace_directory("work".to_string(), "/work".into()).unwrap();
+        remove_workspace_directory("work".to_string(), "/vendor".into())
+            .unwrap();
+        create_workspace("new".to_string()).unwrap();
+        remove_workspace("new".to_string()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&test_env.config_file).unwrap(),
+            r#"# Written by hand
+[metadata]
+version = "0.1.0"
+schema = 1
+
+[workspaces]
+# Personal projects
+default = [
+    "/code", # the main one
+    "/other",
+]
+work = { directories = ["/job"], max_depth = 2 }
+tools = [
+  "/bin",
+  "/sbin",
+  "/opt"
+]
+
+[workspaces.old]
+# Kept for reference
+description = "Archived"
+directories = ["/old"]
+"#
+        );
+    }
+
     #[test]
     #[serial]
     fn test_find_current_workspace() {
         let test_env = TestEnvironment::new().init();
         env::set_current_dir(&test_env.project_dir).unwrap();
         assert_eq!(
-            find_current_workspace().unwrap(),
-            test_env.default_workspace
+            find_current_workspaces(Inference::Specific).unwrap(),
+            vec![test_env.default_workspace.clone()]
         );
         let sub_dir = test_env.project_dir.join("project");
         fs::create_dir(&sub_dir).unwrap();
-        env::set_current_dir(sub_dir).unwrap();
+        env::set_current_dir(&sub_dir).unwrap();
+        assert_eq!(
+            find_current_workspaces(Inference::Specific).unwrap(),
+            vec![test_env.default_workspace.clone()]
+        );
+
+        create_workspace("nested".to_string()).unwrap();
+        add_workspace_directory("nested".to_string(), sub_dir, false).unwrap();
+        assert_eq!(
+            find_current_workspaces(Inference::Specific).unwrap(),
+            ["nested".to_string()]
+        );
+        assert_eq!(
+            find_current_workspaces(Inference::Union).unwrap(),
+            ["nested".to_string(), test_env.default_workspace.clone()]
+        );
+
+        let config = fs::read_to_string(&test_env.config_file).unwrap();
+        fs::write(
+            &test_env.config_file,
+            config + "[settings]\ninfer = \"union\"\n",
+        )
+        .unwrap();
+        assert_eq!(inference(None).unwrap(), Inference::Union);
         assert_eq!(
-            find_current_workspace().unwrap(),
-            test_env.default_workspace
+            inference(Some(Inference::Specific)).unwrap(),
+            Inference::Specific
         );
     }
 
@@ -568,7 +1379,13 @@ mod tests {
         //              another_project/    .git/
         //              subdir/             project/    .git/
 
-        let directories = search_workspace("default".to_string()).unwrap();
+        let directories = search_workspace(
+            "default".to_string(),
+            None,
+            false,
+            &Overrides::default(),
+        )
+        .unwrap();
 
         assert!(directories.contains(&a_project));
         assert!(directories.contains(&another_project));
@@ -576,4 +1393,125 @@ mod tests {
         assert!(!directories.contains(&file_trap));
         assert!(!directories.contains(&dir_trap));
     }
+
+    #[test]
+    #[serial]
+    fn test_search_workspace_table_form() {
+        let test_env = TestEnvironment::new().init();
+        let project_dir_str = test_env.project_dir.to_string_lossy();
+        let version = crate_version!();
+        let table = toml! {
+            [metadata]
+            version = version
+
+            [workspaces.default]
+            directories = [project_dir_str]
+            respect_ignore = false
+        }
+        .to_string();
+        fs::write(&test_env.config_file, table).unwrap();
+
+        let ignored_project = test_env.project_dir.join("ignored");
+        fs::create_dir_all(ignored_project.join(".git")).unwrap();
+        fs::write(test_env.project_dir.join(".gitignore"), "ignored\n")
+            .unwrap();
+
+        let directories = search_workspace(
+            "default".to_string(),
+            None,
+            false,
+            &Overrides::default(),
+        )
+        .unwrap();
+
+        assert!(directories.contains(&ignored_project));
+    }
+
+    #[test]
+    #[serial]
+    fn test_search_workspace_updates_index() {
+        let test_env = TestEnvironment::new().init();
+        let a_project = test_env.project_dir.join("a_project");
+        fs::create_dir_all(a_project.join(".git")).unwrap();
+
+        let directories = search_workspace(
+            "default".to_string(),
+            None,
+            false,
+            &Overrides::default(),
+        )
+        .unwrap();
+        let cache_dir = CACHE_DIR.lock().unwrap().clone();
+        let index = Index::load(&cache_dir, "default").unwrap();
+        assert_eq!(index.projects, directories);
+
+        let another_project = test_env.project_dir.join("another_project");
+        fs::create_dir_all(another_project.join(".git")).unwrap();
+        let directories = search_workspace(
+            "default".to_string(),
+            None,
+            true,
+            &Overrides::default(),
+        )
+        .unwrap();
+        assert_eq!(directories, vec![a_project, another_project]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_list_workspaces() {
+        let test_env = TestEnvironment::new().init();
+        let project = test_env.project_dir.join("project");
+        fs::create_dir_all(project.join(".git")).unwrap();
+
+        let workspaces = list_workspaces(None, true, None).unwrap();
+        assert_eq!(workspaces.len(), 1);
+        assert_eq!(workspaces[0].name, "default");
+        assert_eq!(workspaces[0].directories[0].path, test_env.project_dir);
+        assert_eq!(workspaces[0].directories[0].projects, Some(vec![project]));
+
+        let workspaces =
+            list_workspaces(Some("default".to_string()), false, None).unwrap();
+        assert_eq!(workspaces[0].directories[0].projects, None);
+        assert!(matches!(
+            list_workspaces(Some("undefined".to_string()), false, None),
+            Err(Error::UndefinedWorkspace(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_filter() {
+        let test_env = TestEnvironment::new().init();
+        for project in ["api", "web-api", "website"] {
+            fs::create_dir_all(test_env.project_dir.join(project).join(".git"))
+                .unwrap();
+        }
+
+        let paths = filter(
+            &["default".to_string()],
+            None,
+            false,
+            &Overrides::default(),
+            "api",
+        )
+        .unwrap();
+        // Queries match the whole path, so the randomly named temporary
+        // directory can bring in the remaining project after the others
+        let relative: Vec<&Path> = paths
+            .iter()
+            .map(|path| path.strip_prefix(&test_env.project_dir).unwrap())
+            .collect();
+        assert_eq!(relative[..2], [Path::new("api"), Path::new("web-api")]);
+        assert!(matches!(
+            filter(
+                &["default".to_string()],
+                None,
+                false,
+                &Overrides::default(),
+                "'nothing"
+            ),
+            Err(Error::NoMatch(_))
+        ));
+    }
 }
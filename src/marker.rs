@@ -0,0 +1,211 @@
+use std::{ffi::OsString, fs, io, path::Path, sync::OnceLock};
+
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// A rule deciding whether a directory is a project, as written in the config.
+///
+/// A pattern is either a file name or a glob matched against the names of the
+/// entries in a directory. Patterns ending with `/` only match directories.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Marker {
+    Pattern(String),
+    Any { any: Vec<Marker> },
+    All { all: Vec<Marker> },
+}
+
+/// Compiled markers where a directory is a project if any of them match
+pub struct Markers {
+    rules: Vec<Rule>,
+}
+
+enum Rule {
+    Name { name: String, dir_only: bool },
+    Glob { glob: GlobMatcher, dir_only: bool },
+    Any(Vec<Rule>),
+    All(Vec<Rule>),
+}
+
+impl Markers {
+    pub fn compile(markers: &[Marker]) -> Result<Self, Error> {
+        Ok(Self {
+            rules: markers
+                .iter()
+                .map(Rule::compile)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_project(&self, directory: &Path) -> Result<bool, io::Error> {
+        let listing = Listing::new(directory);
+        for rule in &self.rules {
+            if rule.matches(&listing)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Default for Markers {
+    /// A directory containing a `.git` directory
+    fn default() -> Self {
+        Self {
+            rules: vec![Rule::Name {
+                name: ".git".to_string(),
+                dir_only: true,
+            }],
+        }
+    }
+}
+
+impl Rule {
+    fn compile(marker: &Marker) -> Result<Self, Error> {
+        Ok(match marker {
+            Marker::Pattern(pattern) => {
+                let (pattern, dir_only) = match pattern.strip_suffix('/') {
+                    Some(pattern) => (pattern, true),
+                    None => (pattern.as_str(), false),
+                };
+                if pattern.contains(['*', '?', '[', '{']) {
+                    Rule::Glob {
+                        glob: Glob::new(pattern)
+                            .map_err(|err| {
+                                Error::InvalidMarker(pattern.to_string(), err)
+                            })?
+                            .compile_matcher(),
+                        dir_only,
+                    }
+                } else {
+                    Rule::Name {
+                        name: pattern.to_string(),
+                        dir_only,
+                    }
+                }
+            }
+            Marker::Any { any } => Rule::Any(
+                any.iter().map(Rule::compile).collect::<Result<_, _>>()?,
+            ),
+            Marker::All { all } => Rule::All(
+                all.iter().map(Rule::compile).collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    fn matches(&self, listing: &Listing) -> Result<bool, io::Error> {
+        match self {
+            Rule::Name { name, dir_only } => {
+                let path = listing.directory.join(name);
+                Ok(path.try_exists()? && (!dir_only || path.is_dir()))
+            }
+            Rule::Glob { glob, dir_only } => {
+                Ok(listing.entries()?.iter().any(|(name, is_dir)| {
+                    glob.is_match(name) && (!dir_only || *is_dir)
+                }))
+            }
+            Rule::Any(rules) => {
+                for rule in rules {
+                    if rule.matches(listing)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Rule::All(rules) => {
+                for rule in rules {
+                    if !rule.matches(listing)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Entries of a directory, only read once a glob needs them
+struct Listing<'a> {
+    directory: &'a Path,
+    entries: OnceLock<Vec<(OsString, bool)>>,
+}
+
+impl<'a> Listing<'a> {
+    fn new(directory: &'a Path) -> Self {
+        Self {
+            directory,
+            entries: OnceLock::new(),
+        }
+    }
+
+    fn entries(&self) -> Result<&Vec<(OsString, bool)>, io::Error> {
+        if let Some(entries) = self.entries.get() {
+            return Ok(entries);
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.directory)? {
+            let entry = entry?;
+            entries.push((entry.file_name(), entry.path().is_dir()));
+        }
+        Ok(self.entries.get_or_init(|| entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    fn markers(toml: &str) -> Markers {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            markers: Vec<Marker>,
+        }
+        let wrapper: Wrapper = toml::from_str(toml).unwrap();
+        Markers::compile(&wrapper.markers).unwrap()
+    }
+
+    #[test]
+    fn default_requires_git_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let repository = temp_dir.join("repository");
+        fs::create_dir_all(repository.join(".git")).unwrap();
+        let worktree = temp_dir.join("worktree");
+        fs::create_dir(&worktree).unwrap();
+        fs::write(worktree.join(".git"), "gitdir: ../repository").unwrap();
+
+        assert!(Markers::default().is_project(&repository).unwrap());
+        assert!(!Markers::default().is_project(&worktree).unwrap());
+        assert!(markers(r#"markers = [".git"]"#)
+            .is_project(&worktree)
+            .unwrap());
+    }
+
+    #[test]
+    fn globs_and_combinators() {
+        let temp_dir = TempDir::new().unwrap();
+        let solution = temp_dir.join("solution");
+        fs::create_dir(&solution).unwrap();
+        fs::write(solution.join("app.sln"), "").unwrap();
+        let node = temp_dir.join("node");
+        fs::create_dir_all(node.join("node_modules")).unwrap();
+        fs::write(node.join("package.json"), "").unwrap();
+        let partial = temp_dir.join("partial");
+        fs::create_dir(&partial).unwrap();
+        fs::write(partial.join("package.json"), "").unwrap();
+
+        let markers = markers(
+            r#"markers = [
+                "*.sln",
+                { any = [".hg/", ".jj/"] },
+                { all = ["package.json", "node_modules/"] },
+            ]"#,
+        );
+
+        assert!(markers.is_project(&solution).unwrap());
+        assert!(markers.is_project(&node).unwrap());
+        assert!(!markers.is_project(&partial).unwrap());
+    }
+}
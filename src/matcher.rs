@@ -0,0 +1,268 @@
+//! Fuzzy matching of a pattern against paths, scoring matches the way fzf
+//! does: every pattern character has to appear in order, and matches are
+//! rewarded for landing on word boundaries, path separators, the basename
+//! and for following each other, while gaps between them are penalised.
+
+const SCORE_MATCH: i64 = 16;
+const PENALTY_GAP_START: i64 = -3;
+const PENALTY_GAP_EXTENSION: i64 = -1;
+/// Bonus for a match right after a path separator
+const BONUS_SEPARATOR: i64 = 9;
+/// Bonus for a match at the start of a word, e.g. after `-`, `_` or a space
+const BONUS_BOUNDARY: i64 = 8;
+/// Bonus for a match on an upper case character following a lower case one
+const BONUS_CAMEL_CASE: i64 = 7;
+/// Bonus for a match directly following the previous match, which is at
+/// least as much as skipping a single character costs
+const BONUS_CONSECUTIVE: i64 = -(PENALTY_GAP_START + PENALTY_GAP_EXTENSION);
+/// Bonus for a match within the last path component
+const BONUS_BASENAME: i64 = 2;
+/// The bonus of the first pattern character is multiplied by this
+const BONUS_FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+pub struct Matcher {
+    pattern: Vec<char>,
+    case_sensitive: bool,
+}
+
+/// How well a pattern matched a text
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Match {
+    pub score: i64,
+    /// Indices of the matched characters in the text, in ascending order
+    pub positions: Vec<usize>,
+}
+
+/// The best way to match the pattern up to a character at some position
+#[derive(Clone, Copy)]
+struct Cell {
+    score: i64,
+    /// Bonus of the first character of the consecutive chunk this ends
+    chunk_bonus: i64,
+    /// Position the previous pattern character was matched at
+    from: usize,
+}
+
+impl Matcher {
+    /// Create a matcher for a pattern, matching case sensitively only if the
+    /// pattern contains an upper case character
+    pub fn new(pattern: &str) -> Self {
+        let case_sensitive = pattern.chars().any(char::is_uppercase);
+        Self {
+            pattern: pattern
+                .chars()
+                .map(|ch| fold_case(ch, case_sensitive))
+                .collect(),
+            case_sensitive,
+        }
+    }
+
+    /// Find the best match of the pattern in a text, or `None` if the text
+    /// does not contain every character of the pattern in order
+    pub fn find(&self, text: &str) -> Option<Match> {
+        if self.pattern.is_empty() {
+            return Some(Match::default());
+        }
+        let original: Vec<char> = text.chars().collect();
+        let text: Vec<char> = original
+            .iter()
+            .map(|ch| fold_case(*ch, self.case_sensitive))
+            .collect();
+        if !is_subsequence(&self.pattern, &text) {
+            return None;
+        }
+        let bonuses = bonuses(&original);
+
+        // The best match of the pattern so far with its last character matched
+        // at each position of the text, for every character of the pattern
+        let mut rows: Vec<Vec<Option<Cell>>> =
+            Vec::with_capacity(self.pattern.len());
+        for (i, pattern_ch) in self.pattern.iter().enumerate() {
+            let mut row: Vec<Option<Cell>> = vec![None; text.len()];
+            // Best score of the previous row with a gap up to this position,
+            // and where that score was reached
+            let mut gapped: Option<(i64, usize)> = None;
+            for (j, text_ch) in text.iter().enumerate() {
+                if let (Some(previous), Some(k)) =
+                    (rows.last(), j.checked_sub(2))
+                {
+                    let extended = gapped.map(|(score, from)| {
+                        (score + PENALTY_GAP_EXTENSION, from)
+                    });
+                    let started = previous[k]
+                        .map(|cell| (cell.score + PENALTY_GAP_START, k));
+                    gapped = best(extended, started, |(score, _)| *score);
+                }
+                if text_ch != pattern_ch {
+                    continue;
+                }
+                if i == 0 {
+                    row[j] = Some(Cell {
+                        score: SCORE_MATCH
+                            + bonuses[j] * BONUS_FIRST_CHAR_MULTIPLIER,
+                        chunk_bonus: bonuses[j],
+                        from: j,
+                    });
+                    continue;
+                }
+                // A consecutive match keeps the bonus of the start of its
+                // chunk, so `proj` in `/work/proj` outranks the separated
+                // `/p/r/o/j`
+                let consecutive = j
+                    .checked_sub(1)
+                    .and_then(|k| rows[i - 1][k].map(|cell| (cell, k)))
+                    .map(|(cell, k)| Cell {
+                        score: cell.score
+                            + SCORE_MATCH
+                            + bonuses[j]
+                                .max(cell.chunk_bonus)
+                                .max(BONUS_CONSECUTIVE),
+                        chunk_bonus: cell.chunk_bonus,
+                        from: k,
+                    });
+                let gapped = gapped.map(|(score, from)| Cell {
+                    score: score + SCORE_MATCH + bonuses[j],
+                    chunk_bonus: bonuses[j],
+                    from,
+                });
+                row[j] = best(gapped, consecutive, |cell| cell.score);
+            }
+            rows.push(row);
+        }
+
+        let (mut position, last) = rows
+            .last()?
+            .iter()
+            .enumerate()
+            .filter_map(|(j, cell)| cell.map(|cell| (j, cell)))
+            .max_by_key(|(_, cell)| cell.score)?;
+        let mut positions = vec![position];
+        for i in (1..rows.len()).rev() {
+            position = rows[i][position]?.from;
+            positions.push(position);
+        }
+        positions.reverse();
+        Some(Match {
+            score: last.score,
+            positions,
+        })
+    }
+}
+
+/// The better of two options, preferring the second one on ties
+fn best<T>(a: Option<T>, b: Option<T>, score: impl Fn(&T) -> i64) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if score(&a) > score(&b) { a } else { b }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn fold_case(ch: char, case_sensitive: bool) -> char {
+    if case_sensitive {
+        ch
+    } else {
+        ch.to_lowercase().next().unwrap_or(ch)
+    }
+}
+
+fn is_subsequence(pattern: &[char], text: &[char]) -> bool {
+    let mut text = text.iter();
+    pattern
+        .iter()
+        .all(|pattern_ch| text.any(|ch| ch == pattern_ch))
+}
+
+/// Bonus for matching each character of a text, based on where it is
+fn bonuses(text: &[char]) -> Vec<i64> {
+    let basename_start = text
+        .iter()
+        .rposition(|ch| *ch == '/')
+        .map_or(0, |separator| separator + 1);
+    text.iter()
+        .enumerate()
+        .map(|(j, ch)| {
+            let bonus = match j.checked_sub(1).map(|k| text[k]) {
+                None => BONUS_BOUNDARY,
+                Some('/') => BONUS_SEPARATOR,
+                Some(' ' | '-' | '_' | '.') => BONUS_BOUNDARY,
+                Some(previous)
+                    if previous.is_lowercase() && ch.is_uppercase() =>
+                {
+                    BONUS_CAMEL_CASE
+                }
+                Some(_) => 0,
+            };
+            if j >= basename_start {
+                bonus + BONUS_BASENAME
+            } else {
+                bonus
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rank<'a>(pattern: &str, texts: &[&'a str]) -> Vec<&'a str> {
+        let matcher = Matcher::new(pattern);
+        let mut scored: Vec<(i64, &str)> = texts
+            .iter()
+            .filter_map(|text| {
+                matcher.find(text).map(|found| (found.score, *text))
+            })
+            .collect();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.into_iter().map(|(_, text)| text).collect()
+    }
+
+    #[test]
+    fn requires_subsequence() {
+        let matcher = Matcher::new("pf");
+        assert!(matcher.find("/code/project-finder").is_some());
+        assert!(matcher.find("/code/pass/fail").is_some());
+        assert!(matcher.find("/code/fp").is_none());
+        assert_eq!(Matcher::new("").find("/anything"), Some(Match::default()));
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(Matcher::new("finder").find("/code/Finder").is_some());
+        assert!(Matcher::new("Finder").find("/code/Finder").is_some());
+        assert!(Matcher::new("Finder").find("/code/finder").is_none());
+    }
+
+    #[test]
+    fn prefers_consecutive_matches() {
+        assert_eq!(
+            rank("proj", &["/p/r/o/j", "/work/proj"]),
+            vec!["/work/proj", "/p/r/o/j"]
+        );
+    }
+
+    #[test]
+    fn prefers_boundaries_and_basename() {
+        assert_eq!(
+            rank("pf", &["/code/pretty/foo/x", "/code/project-finder"]),
+            vec!["/code/project-finder", "/code/pretty/foo/x"]
+        );
+        assert_eq!(
+            rank("api", &["/api/website", "/work/website/api"]),
+            vec!["/work/website/api", "/api/website"]
+        );
+        assert_eq!(
+            rank("fb", &["/code/fooBar", "/code/foobar"]),
+            vec!["/code/fooBar", "/code/foobar"]
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let found = Matcher::new("pf").find("/code/project-finder").unwrap();
+        assert_eq!(found.positions, vec![6, 14]);
+        let found = Matcher::new("proj").find("/p/r/work/proj").unwrap();
+        assert_eq!(found.positions, vec![10, 11, 12, 13]);
+    }
+}
@@ -0,0 +1,154 @@
+//! Upgrades of config files written for older schemas. The schema version is
+//! stored as `metadata.schema` and is independent of the crate version, which
+//! is only recorded as `metadata.version` to tell which binary last wrote the
+//! file. Configs without a schema version predate it and are at schema 0.
+//! Migrations edit the document in place so comments and formatting survive.
+
+use std::path::{Path, PathBuf};
+
+use clap::crate_version;
+use toml_edit::{DocumentMut, Item, TableLike};
+use tracing::info;
+
+use crate::{atomic, error::Error};
+
+/// Schema version of configs written by this binary
+pub const SCHEMA: u32 = MIGRATIONS.len() as u32;
+
+type Migration = fn(&mut DocumentMut) -> Result<(), Error>;
+
+/// Migrations upgrading a config by one schema version, where the migration
+/// at index `n` upgrades schema `n` to `n + 1`
+const MIGRATIONS: [Migration; 1] = [introduce_schema];
+
+/// Whether a config is written for the current schema and needs no upgrade
+pub fn is_current(config_file: &Path, content: &str) -> Result<bool, Error> {
+    let config: DocumentMut = content.parse()?;
+    Ok(supported_schema(config_file, &config)? == SCHEMA)
+}
+
+/// Upgrade a config step by step to the current schema, backing up the
+/// original file next to it first. Returns the upgraded content, or `None`
+/// if the config is already up to date.
+pub fn upgrade(
+    config_file: &Path,
+    content: &str,
+) -> Result<Option<String>, Error> {
+    let mut config: DocumentMut = content.parse()?;
+    let schema = supported_schema(config_file, &config)?;
+    if schema == SCHEMA {
+        return Ok(None);
+    }
+
+    let backup = backup_file(config_file, schema);
+    info!("backing up config for schema {} to {:?}", schema, backup);
+    atomic::write(&backup, content)?;
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(schema as usize)
+    {
+        info!("migrating config from schema {} to {}", from, from + 1);
+        migration(&mut config)?;
+    }
+    let metadata = metadata(&mut config)
+        .ok_or_else(|| Error::InvalidSchema(config_file.to_path_buf()))?;
+    metadata.insert("schema", toml_edit::value(i64::from(SCHEMA)));
+    metadata.insert("version", toml_edit::value(crate_version!()));
+    Ok(Some(config.to_string()))
+}
+
+/// The schema of a config, refusing configs written by a newer binary
+fn supported_schema(
+    config_file: &Path,
+    config: &DocumentMut,
+) -> Result<u32, Error> {
+    let schema = schema(config_file, config)?;
+    if schema > SCHEMA {
+        return Err(Error::NewerConfig(
+            config_file.to_path_buf(),
+            schema,
+            SCHEMA,
+        ));
+    }
+    Ok(schema)
+}
+
+fn schema(config_file: &Path, config: &DocumentMut) -> Result<u32, Error> {
+    let schema = config
+        .get("metadata")
+        .and_then(Item::as_table_like)
+        .and_then(|metadata| metadata.get("schema"));
+    match schema {
+        None => Ok(0),
+        Some(schema) => schema
+            .as_integer()
+            .and_then(|schema| u32::try_from(schema).ok())
+            .ok_or_else(|| Error::InvalidSchema(config_file.to_path_buf())),
+    }
+}
+
+/// `<config>.schema-<n>.bak`, so backups of different schemas never replace
+/// each other
+fn backup_file(config_file: &Path, schema: u32) -> PathBuf {
+    let mut name = config_file.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".schema-{}.bak", schema));
+    config_file.with_file_name(name)
+}
+
+fn metadata(config: &mut DocumentMut) -> Option<&mut dyn TableLike> {
+    config
+        .entry("metadata")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+}
+
+/// Schema 1 introduced the schema version itself, along with the typed
+/// workspace tables which accept every workspace written before
+fn introduce_schema(config: &mut DocumentMut) -> Result<(), Error> {
+    config.entry("workspaces").or_insert_with(toml_edit::table);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn upgrade_legacy_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.join("projectfinder.toml");
+        let legacy = "[metadata]\nversion = \"0.1.0\"\n\n[workspaces]\n# Mine\na = [\"/code\"]\n";
+
+        let upgraded = upgrade(&config_file, legacy).unwrap().unwrap();
+        assert!(upgraded.contains("# Mine\na = [\"/code\"]\n"));
+        let upgraded: toml::Table = toml::from_str(&upgraded).unwrap();
+        assert_eq!(upgraded["metadata"]["schema"].as_integer(), Some(1));
+        assert_eq!(upgraded["workspaces"]["a"][0].as_str(), Some("/code"));
+        assert_eq!(
+            fs::read_to_string(
+                temp_dir.join("projectfinder.toml.schema-0.bak")
+            )
+            .unwrap(),
+            legacy
+        );
+
+        let current = toml::to_string(&upgraded).unwrap();
+        assert_eq!(upgrade(&config_file, &current).unwrap(), None);
+        assert!(is_current(&config_file, &current).unwrap());
+        assert!(!is_current(&config_file, legacy).unwrap());
+    }
+
+    #[test]
+    fn refuse_newer_and_invalid_schemas() {
+        let config_file = Path::new("/projectfinder.toml");
+        assert!(matches!(
+            upgrade(config_file, "[metadata]\nschema = 1000\n"),
+            Err(Error::NewerConfig(_, 1000, SCHEMA))
+        ));
+        assert!(matches!(
+            upgrade(config_file, "[metadata]\nschema = \"one\"\n"),
+            Err(Error::InvalidSchema(_))
+        ));
+    }
+}
@@ -4,6 +4,15 @@ use tracing::{debug, info, trace};
 
 use crate::error::Error;
 
+/// Resolve an XDG base directory variable, falling back to its default
+/// location relative to `$HOME` when it is unset or empty
+pub fn xdg_dir(variable: &str, default: &str) -> Result<PathBuf, Error> {
+    match env::var_os(variable) {
+        Some(value) if !value.is_empty() => Ok(value.into()),
+        _ => Ok(resolve_path_variables("~".into())?.join(default)),
+    }
+}
+
 pub fn resolve_path_variables(path: PathBuf) -> Result<PathBuf, Error> {
     debug!("resolving variables in path: {:?}", path);
     Ok(shellexpand::full(
@@ -13,6 +22,7 @@ pub fn resolve_path_variables(path: PathBuf) -> Result<PathBuf, Error> {
     .to_string()
     .into())
 }
+#[allow(dead_code)]
 pub fn substitute_path_with_variables(path: PathBuf) -> Result<PathBuf, Error> {
     debug!("substituting following path with variables: {:?}", path);
     trace!("cleaning out non-path variables");
@@ -51,7 +61,7 @@ pub fn substitute_path_with_variables(path: PathBuf) -> Result<PathBuf, Error> {
         return Ok(new_path);
     }
     debug!("did not find any variables that matches path ancestry");
-    return Ok(path);
+    Ok(path)
 }
 
 #[cfg(test)]
@@ -0,0 +1,209 @@
+//! Details about a project shown next to the result list. Previews are made
+//! on a thread of their own so moving through the results never waits on
+//! git or a slow preview command.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use tracing::{debug, warn};
+
+use crate::tui::Event;
+
+/// Lines of the README shown by the built-in preview
+const README_LINES: usize = 10;
+/// Commits shown by the built-in preview
+const COMMITS: usize = 5;
+
+/// Start making previews, returning where to send the paths to preview. Only
+/// the latest path sent while a preview was being made is previewed next, and
+/// the lines are sent back as [`Event::Preview`].
+pub fn spawn(
+    command: Option<String>,
+    events: Sender<io::Result<Event>>,
+) -> Sender<PathBuf> {
+    let (sender, paths) = mpsc::channel::<PathBuf>();
+    thread::spawn(move || {
+        while let Ok(mut path) = paths.recv() {
+            while let Ok(newer) = paths.try_recv() {
+                path = newer;
+            }
+            let lines = preview(&path, command.as_deref());
+            if events.send(Ok(Event::Preview(path, lines))).is_err() {
+                break;
+            }
+        }
+    });
+    sender
+}
+
+/// Preview a project with a command, in which `{}` is replaced by the path,
+/// or with the built-in preview when there is no command
+pub fn preview(path: &Path, command: Option<&str>) -> Vec<String> {
+    match command {
+        Some(command) => run(command, path),
+        None => builtin(path),
+    }
+}
+
+fn run(command: &str, path: &Path) -> Vec<String> {
+    let command = command.replace("{}", &quote(path));
+    debug!("previewing with {}", command);
+    let output = match Command::new("sh")
+        .args(["-c", &command])
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) => {
+            warn!("could not run preview command {}: {}", command, err);
+            return vec![err.to_string()];
+        }
+    };
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text += &String::from_utf8_lossy(&output.stderr);
+    text.lines().map(sanitize).collect()
+}
+
+/// Current branch and recent commits of the project, its top-level files and
+/// the start of its README
+fn builtin(path: &Path) -> Vec<String> {
+    let mut lines = Vec::new();
+    if path.join(".git").exists() {
+        if let Some(branch) = git(path, &["rev-parse", "--abbrev-ref", "HEAD"])
+        {
+            lines.push(format!("Branch: {}", branch.trim()));
+        }
+        let count = format!("-{}", COMMITS);
+        if let Some(log) = git(path, &["log", &count, "--format=%h %s"]) {
+            lines.push("Recent commits:".to_string());
+            lines.extend(log.lines().map(|line| format!("  {}", line)));
+        }
+        lines.push(String::new());
+    }
+
+    let mut entries: Vec<(bool, String)> = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+                (!is_dir, entry.file_name().to_string_lossy().into_owned())
+            })
+            .filter(|(_, name)| name != ".git")
+            .collect(),
+        Err(err) => return vec![err.to_string()],
+    };
+    // Directories first, then files, each in alphabetical order
+    entries.sort();
+    lines.push("Files:".to_string());
+    for (is_file, name) in &entries {
+        let suffix = if *is_file { "" } else { "/" };
+        lines.push(format!("  {}{}", sanitize(name), suffix));
+    }
+
+    let readme = entries.iter().find(|(is_file, name)| {
+        *is_file && name.to_ascii_lowercase().starts_with("readme")
+    });
+    if let Some((_, name)) = readme {
+        if let Ok(content) = fs::read_to_string(path.join(name)) {
+            lines.push(String::new());
+            lines.push(format!("{}:", sanitize(name)));
+            lines.extend(
+                content
+                    .lines()
+                    .take(README_LINES)
+                    .map(|line| format!("  {}", sanitize(line))),
+            );
+        }
+    }
+    lines
+}
+
+fn git(path: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Quote a path for `sh`
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// Make a line safe to draw, dropping escape sequences and other control
+/// characters that would move the cursor or change the terminal
+fn sanitize(line: &str) -> String {
+    let mut sanitized = String::new();
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\x1b' => {
+                // Skip a CSI sequence up to its final byte, or the character
+                // following a lone escape
+                if chars.next() == Some('[') {
+                    for ch in chars.by_ref() {
+                        if ('@'..='~').contains(&ch) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => sanitized += "    ",
+            ch if ch.is_control() => {}
+            ch => sanitized.push(ch),
+        }
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn builtin_preview() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.join("src")).unwrap();
+        fs::write(temp_dir.join("Cargo.toml"), "").unwrap();
+        fs::write(temp_dir.join("README.md"), "# Title\n\tText\x1b[1m!")
+            .unwrap();
+
+        assert_eq!(
+            preview(&temp_dir, None),
+            vec![
+                "Files:",
+                "  src/",
+                "  Cargo.toml",
+                "  README.md",
+                "",
+                "README.md:",
+                "  # Title",
+                "      Text!",
+            ]
+        );
+    }
+
+    #[test]
+    fn command_preview() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.join("it's a project");
+        assert_eq!(
+            preview(&path, Some("echo {}; echo done")),
+            vec![path.to_string_lossy().into_owned(), "done".to_string()]
+        );
+    }
+}
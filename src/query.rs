@@ -0,0 +1,357 @@
+//! The fzf-style query syntax understood by the search prompt.
+//!
+//! A query is made of space separated terms which all have to match, while
+//! terms joined by `|` only need one of them to match. Each term is matched
+//! fuzzily unless it is prefixed or suffixed by an operator:
+//!
+//! | Term      | Matches paths                                  |
+//! |-----------|------------------------------------------------|
+//! | `'exact`  | containing `exact`                             |
+//! | `^prefix` | with a path component starting with `prefix`   |
+//! | `suffix$` | ending with `suffix`                           |
+//! | `^name$`  | with a path component equal to `name`          |
+//! | `!term`   | not matching any of the above, e.g. `!^tmp`    |
+//!
+//! A space can be part of a term by escaping it as `\ `.
+
+use std::{cmp::Reverse, path::PathBuf};
+
+use crate::matcher::{Match, Matcher};
+
+/// Lead over the runner-up making the best match clear, worth about two
+/// characters matched on word boundaries
+const CLEAR_LEAD: i64 = 48;
+
+pub struct Query {
+    /// Groups that all have to match, each made of terms of which at least
+    /// one has to match
+    groups: Vec<Vec<Term>>,
+}
+
+struct Term {
+    kind: Kind,
+    inverse: bool,
+    text: String,
+    case_sensitive: bool,
+    matcher: Matcher,
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Fuzzy,
+    Exact,
+    Prefix,
+    Suffix,
+    Equal,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Self {
+        let mut groups: Vec<Vec<Term>> = Vec::new();
+        let mut join = false;
+        for token in tokenize(input) {
+            if token == "|" {
+                join = !groups.is_empty();
+                continue;
+            }
+            let term = match Term::parse(&token) {
+                Some(term) => term,
+                None => continue,
+            };
+            match groups.last_mut() {
+                Some(group) if join => group.push(term),
+                _ => groups.push(vec![term]),
+            }
+            join = false;
+        }
+        Self { groups }
+    }
+
+    /// Match a text against the query, or `None` if it does not match. The
+    /// scores and positions of all matching terms are combined, while an
+    /// empty query matches everything equally.
+    pub fn find(&self, text: &str) -> Option<Match> {
+        let mut found = Match::default();
+        for group in &self.groups {
+            let best = group
+                .iter()
+                .filter_map(|term| term.find(text))
+                .max_by_key(|found| found.score)?;
+            found.score += best.score;
+            found.positions.extend(best.positions);
+        }
+        found.positions.sort_unstable();
+        found.positions.dedup();
+        Some(found)
+    }
+}
+
+/// Keep the paths a scorer matches along with the positions of the matched
+/// characters, best score first. Paths with equal scores are ordered by
+/// length and then by their original order.
+pub fn rank<S>(paths: &[PathBuf], score: S) -> Vec<(PathBuf, Vec<usize>)>
+where
+    S: FnMut(&PathBuf) -> Option<Match>,
+{
+    ranked(paths, score)
+        .into_iter()
+        .map(|(found, path)| (path.clone(), found.positions))
+        .collect()
+}
+
+/// The best path if it is the only match, or if it scores clearly better
+/// than the runner-up so there is no doubt it is the one being looked for
+pub fn clear_best<S>(paths: &[PathBuf], score: S) -> Option<&PathBuf>
+where
+    S: FnMut(&PathBuf) -> Option<Match>,
+{
+    match ranked(paths, score).as_slice() {
+        [(_, best)] => Some(best),
+        [(best, path), (runner_up, _), ..]
+            if best.score - runner_up.score >= CLEAR_LEAD =>
+        {
+            Some(path)
+        }
+        _ => None,
+    }
+}
+
+fn ranked<S>(paths: &[PathBuf], mut score: S) -> Vec<(Match, &PathBuf)>
+where
+    S: FnMut(&PathBuf) -> Option<Match>,
+{
+    let mut matched: Vec<(Match, &PathBuf)> = paths
+        .iter()
+        .filter_map(|path| score(path).map(|found| (found, path)))
+        .collect();
+    matched.sort_by_key(|(found, path)| {
+        (Reverse(found.score), path.as_os_str().len())
+    });
+    matched
+}
+
+impl Term {
+    fn parse(token: &str) -> Option<Self> {
+        let (inverse, token) = match token.strip_prefix('!') {
+            Some(token) => (true, token),
+            None => (false, token),
+        };
+        let (kind, text) = if let Some(text) = token.strip_prefix('\'') {
+            (Kind::Exact, text)
+        } else {
+            let prefix = token.strip_prefix('^');
+            let suffix = prefix
+                .unwrap_or(token)
+                .strip_suffix('$')
+                .filter(|text| !text.is_empty());
+            match (prefix, suffix) {
+                (Some(_), Some(text)) => (Kind::Equal, text),
+                (Some(text), None) => (Kind::Prefix, text),
+                (None, Some(text)) => (Kind::Suffix, text),
+                // Negated terms match exactly, like in fzf
+                (None, None) if inverse => (Kind::Exact, token),
+                (None, None) => (Kind::Fuzzy, token),
+            }
+        };
+        if text.is_empty() {
+            return None;
+        }
+        let case_sensitive = text.chars().any(char::is_uppercase);
+        Some(Self {
+            kind,
+            inverse,
+            text: fold_case(text, case_sensitive),
+            case_sensitive,
+            matcher: Matcher::new(text),
+        })
+    }
+
+    fn find(&self, text: &str) -> Option<Match> {
+        if self.kind == Kind::Fuzzy {
+            return self.matcher.find(text);
+        }
+        let folded = fold_case(text, self.case_sensitive);
+        let length = self.text.chars().count();
+        let start = match self.kind {
+            Kind::Fuzzy | Kind::Exact => folded.find(&self.text),
+            Kind::Prefix => components(&folded)
+                .find(|(_, component)| component.starts_with(&self.text))
+                .map(|(start, _)| start),
+            Kind::Suffix => folded
+                .ends_with(&self.text)
+                .then(|| folded.len() - self.text.len()),
+            Kind::Equal => components(&folded)
+                .find(|(_, component)| *component == self.text)
+                .map(|(start, _)| start),
+        };
+        match (start, self.inverse) {
+            (Some(start), false) => {
+                let start = folded[..start].chars().count();
+                Some(Match {
+                    score: self.matcher.find(text)?.score,
+                    positions: (start..start + length).collect(),
+                })
+            }
+            (None, true) => Some(Match::default()),
+            _ => None,
+        }
+    }
+}
+
+/// Components of a path along with the byte offset they start at
+fn components(path: &str) -> impl Iterator<Item = (usize, &str)> {
+    path.split('/').scan(0, |start, component| {
+        let offset = *start;
+        *start += component.len() + 1;
+        Some((offset, component))
+    })
+}
+
+/// Fold the case of every character on its own, so character positions in
+/// the folded text are the same as in the original
+fn fold_case(text: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        text.to_string()
+    } else {
+        text.chars()
+            .map(|ch| ch.to_lowercase().next().unwrap_or(ch))
+            .collect()
+    }
+}
+
+/// Split a query on whitespace, keeping spaces escaped with `\`
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut chars = input.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(' ') => token.push(' '),
+                Some(ch) => {
+                    token.push('\\');
+                    token.push(ch);
+                }
+                None => token.push('\\'),
+            },
+            ch if ch.is_whitespace() => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            ch => token.push(ch),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(query: &str, text: &str) -> bool {
+        Query::parse(query).find(text).is_some()
+    }
+
+    #[test]
+    fn operators() {
+        assert!(matches("'proj", "/code/project"));
+        assert!(!matches("'pjt", "/code/project"));
+        assert!(matches("pjt", "/code/project"));
+        assert!(matches("^work", "/home/me/work/api"));
+        assert!(!matches("^ork", "/home/me/work/api"));
+        assert!(matches("api$", "/home/me/work/api"));
+        assert!(!matches("work$", "/home/me/work/api"));
+        assert!(matches("^work$", "/home/me/work/api"));
+        assert!(!matches("^wor$", "/home/me/work/api"));
+        assert!(matches("!archive", "/code/project"));
+        assert!(!matches("!archive", "/code/archive/project"));
+        assert!(!matches("!^arch", "/code/archive/project"));
+    }
+
+    #[test]
+    fn and_or() {
+        let query = "^work rust !archive";
+        assert!(matches(query, "/home/me/work/rust-api"));
+        assert!(!matches(query, "/home/me/work/archive/rust-api"));
+        assert!(!matches(query, "/home/me/play/rust-api"));
+
+        let query = "^work api$ | web$";
+        assert!(matches(query, "/home/me/work/api"));
+        assert!(matches(query, "/home/me/work/web"));
+        assert!(!matches(query, "/home/me/work/cli"));
+        assert!(!matches(query, "/home/me/play/web"));
+    }
+
+    #[test]
+    fn escaped_space_and_empty_terms() {
+        assert!(matches("'my\\ project", "/code/my project"));
+        assert!(!matches("'my\\ project", "/code/my/project"));
+        assert!(matches("! ^ '", "/code/anything"));
+        assert_eq!(
+            Query::parse("").find("/code/anything"),
+            Some(Match::default())
+        );
+    }
+
+    #[test]
+    fn smart_case_per_term() {
+        assert!(matches("'API", "/code/API"));
+        assert!(!matches("'API", "/code/api"));
+        assert!(matches("'api", "/code/API"));
+    }
+
+    #[test]
+    fn ranking() {
+        let paths: Vec<PathBuf> =
+            ["/code/api-server", "/code/api", "/code/web", "/api"]
+                .iter()
+                .map(PathBuf::from)
+                .collect();
+        let query = Query::parse("api");
+        let ranked: Vec<PathBuf> =
+            rank(&paths, |path| query.find(&path.to_string_lossy()))
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+        assert_eq!(
+            ranked,
+            vec![
+                PathBuf::from("/api"),
+                PathBuf::from("/code/api"),
+                PathBuf::from("/code/api-server"),
+            ]
+        );
+    }
+
+    #[test]
+    fn clear_best_match() {
+        let paths: Vec<PathBuf> = ["/code/api", "/code/web", "/code/webapp"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let best = |query: &str| {
+            let query = Query::parse(query);
+            clear_best(&paths, |path| query.find(&path.to_string_lossy()))
+                .cloned()
+        };
+        assert_eq!(best("api"), Some(PathBuf::from("/code/api")));
+        assert_eq!(best("web$"), Some(PathBuf::from("/code/web")));
+        assert_eq!(best("web"), None);
+        assert_eq!(best("zzz"), None);
+    }
+
+    #[test]
+    fn positions() {
+        let positions = |query: &str, text: &str| {
+            Query::parse(query).find(text).unwrap().positions
+        };
+        assert_eq!(positions("^wo", "/a/work"), vec![3, 4]);
+        assert_eq!(positions("rk$", "/a/work"), vec![5, 6]);
+        assert_eq!(positions("'or !x", "/a/work"), vec![4, 5]);
+        assert_eq!(positions("^a$ wk", "/a/work"), vec![1, 3, 6]);
+    }
+}
@@ -0,0 +1,920 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+use rayon::{Scope, ThreadPoolBuilder};
+use tracing::{debug, trace, warn};
+
+use crate::{
+    error::Error,
+    index::{Entry, Index, Skipped},
+    marker::Markers,
+};
+
+/// Ignore files read in every directory, ordered from highest to lowest
+/// precedence
+const IGNORE_FILES: [&str; 3] =
+    [".projectfinderignore", ".ignore", ".gitignore"];
+
+/// A directory visited or a path that could not be read
+type Visit = Result<(PathBuf, Entry), Skipped>;
+
+/// Shared state of a single search
+struct Walk<'s> {
+    roots: &'s [Root],
+    visited: &'s Sender<Visit>,
+    previous: Option<&'s Index>,
+    /// Device and inode of every directory claimed for a visit, so one
+    /// reached again through a nested root or a symlink is only searched
+    /// once
+    seen: Mutex<HashSet<(u64, u64)>>,
+    /// Symlinked directories put off until every real path was searched
+    links: Mutex<Vec<Link>>,
+}
+
+/// A symlinked directory left for a later round of the search, with the
+/// index of its root, its depth and the ignore rules of its parent
+type Link = (usize, PathBuf, usize, Arc<IgnoreLevel>);
+
+impl Walk<'_> {
+    fn skip(&self, path: PathBuf, error: io::Error) {
+        debug!("skipping unreadable {:?}: {}", path, error);
+        let _ = self.visited.send(Err(Skipped { path, error }));
+    }
+
+    /// Claim a directory for a visit, unless it was claimed before. A path
+    /// whose metadata cannot be read is claimed, so its visit reports why.
+    fn claim(
+        &self,
+        directory: &Path,
+        metadata: io::Result<fs::Metadata>,
+    ) -> bool {
+        let Ok(metadata) = metadata else {
+            return true;
+        };
+        let claimed = self
+            .seen
+            .lock()
+            .unwrap()
+            .insert((metadata.dev(), metadata.ino()));
+        if !claimed {
+            debug!("skipping directory already searched: {:?}", directory);
+        }
+        claimed
+    }
+}
+
+pub struct Searcher {
+    markers: Markers,
+    respect_ignore: bool,
+    threads: usize,
+    global_ignore: Gitignore,
+    exclude: Exclude,
+    max_depth: Option<usize>,
+    min_depth: Option<usize>,
+    follow_symlinks: bool,
+}
+
+/// A directory to search, with settings of its own taking precedence over
+/// those of the [`Searcher`]
+pub struct Root {
+    path: PathBuf,
+    /// Skipped in addition to what the searcher excludes
+    exclude: Exclude,
+    max_depth: Option<usize>,
+    min_depth: Option<usize>,
+}
+
+impl Root {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            exclude: Exclude::default(),
+            max_depth: None,
+            min_depth: None,
+        }
+    }
+
+    pub fn exclude(mut self, exclude: Exclude) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn min_depth(mut self, min_depth: Option<usize>) -> Self {
+        self.min_depth = min_depth;
+        self
+    }
+}
+
+impl From<PathBuf> for Root {
+    fn from(path: PathBuf) -> Self {
+        Self::new(path)
+    }
+}
+
+/// Globs of directories to skip. Globs containing a `/` are matched against
+/// the path relative to the searched root while other globs are matched
+/// against the name of the directory, so `target` skips every `target`
+/// directory and `archive/*` only the directories within `archive`.
+#[derive(Default)]
+pub struct Exclude {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Exclude {
+    pub fn compile(globs: &[String]) -> Result<Self, Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for glob in globs {
+            let compiled = Glob::new(glob.trim_end_matches('/'))
+                .map_err(|err| Error::InvalidExclude(glob.clone(), err))?;
+            if glob.trim_end_matches('/').contains('/') {
+                paths.add(compiled);
+            } else {
+                names.add(compiled);
+            }
+        }
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .map_err(|err| Error::InvalidExclude(globs.join(", "), err))
+        };
+        Ok(Self {
+            names: build(names)?,
+            paths: build(paths)?,
+        })
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        relative
+            .file_name()
+            .is_some_and(|name| self.names.is_match(name))
+            || self.paths.is_match(relative)
+    }
+}
+
+impl Searcher {
+    /// Create a searcher walking with the given amount of threads, where `0`
+    /// lets the thread pool pick one per available core
+    pub fn new(markers: Markers, respect_ignore: bool, threads: usize) -> Self {
+        let global_ignore = if respect_ignore {
+            let (global_ignore, err) = Gitignore::global();
+            if let Some(err) = err {
+                warn!("could not fully parse global ignore file: {}", err);
+            }
+            global_ignore
+        } else {
+            Gitignore::empty()
+        };
+        Self {
+            markers,
+            respect_ignore,
+            threads,
+            global_ignore,
+            exclude: Exclude::default(),
+            max_depth: None,
+            min_depth: None,
+            follow_symlinks: true,
+        }
+    }
+
+    pub fn exclude(mut self, exclude: Exclude) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Stop descending below a depth, where the roots are at depth 0
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Only find projects from a depth on, still descending into the
+    /// projects above it
+    pub fn min_depth(mut self, min_depth: Option<usize>) -> Self {
+        self.min_depth = min_depth;
+        self
+    }
+
+    /// Whether to descend into symlinked directories. Roots are followed
+    /// either way, and a link to a directory searched through another path
+    /// is never followed.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Search all roots in parallel, reusing what a previous index knows
+    /// about directories that have not been modified since. Projects are
+    /// grouped by root in the order the roots were given and sorted by path
+    /// within each root. Directories that cannot be read and broken symlinks
+    /// are left out and listed in [`Index::skipped`] rather than failing the
+    /// whole search.
+    ///
+    /// A directory is searched once, from the first root containing it.
+    /// Symlinked directories are searched in rounds after the real paths, in
+    /// order, so a directory is found by its real path whenever it can be.
+    pub fn index(
+        &self,
+        roots: Vec<Root>,
+        previous: Option<&Index>,
+    ) -> Result<Index, Error> {
+        let pool =
+            ThreadPoolBuilder::new().num_threads(self.threads).build()?;
+        let (sender, receiver) = mpsc::channel();
+        let walk = Walk {
+            roots: &roots,
+            visited: &sender,
+            previous,
+            seen: Mutex::new(HashSet::new()),
+            links: Mutex::new(Vec::new()),
+        };
+        // Roots are claimed up front, so a nested root is searched as itself
+        // rather than as part of the root containing it
+        let claimed: Vec<usize> = (0..roots.len())
+            .filter(|&index| {
+                let path = &roots[index].path;
+                walk.claim(path, fs::metadata(path))
+            })
+            .collect();
+        pool.scope(|scope| {
+            for index in claimed {
+                let walk = &walk;
+                let root = roots[index].path.clone();
+                scope.spawn(move |scope| {
+                    self.search_directory(scope, walk, index, root, 0, None)
+                });
+            }
+        });
+        loop {
+            let mut links = std::mem::take(&mut *walk.links.lock().unwrap());
+            if links.is_empty() {
+                break;
+            }
+            links.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+            pool.scope(|scope| {
+                for (root, link, depth, level) in links {
+                    if !walk.claim(&link, fs::metadata(&link)) {
+                        continue;
+                    }
+                    let walk = &walk;
+                    scope.spawn(move |scope| {
+                        self.search_directory(
+                            scope,
+                            walk,
+                            root,
+                            link,
+                            depth,
+                            Some(level),
+                        )
+                    });
+                }
+            });
+        }
+        drop(sender);
+
+        let mut projects = Vec::new();
+        let mut directories = HashMap::new();
+        let mut skipped = Vec::new();
+        for visit in receiver {
+            match visit {
+                Ok((directory, entry)) => {
+                    if entry.project {
+                        projects.push((entry.root, directory.clone()));
+                    }
+                    directories.insert(directory, entry);
+                }
+                Err(skip) => skipped.push(skip),
+            }
+        }
+        projects.sort();
+        skipped.sort_by(|a: &Skipped, b| a.path.cmp(&b.path));
+        skipped.dedup_by(|a, b| a.path == b.path);
+        Ok(Index {
+            settings: String::new(),
+            projects: projects
+                .into_iter()
+                .map(|(_, directory)| directory)
+                .collect(),
+            directories,
+            skipped,
+        })
+    }
+
+    fn search_directory<'s>(
+        &'s self,
+        scope: &Scope<'s>,
+        walk: &'s Walk<'s>,
+        root: usize,
+        directory: PathBuf,
+        depth: usize,
+        parent: Option<Arc<IgnoreLevel>>,
+    ) {
+        if let Err(err) = self.visit_directory(
+            scope,
+            walk,
+            root,
+            directory.clone(),
+            depth,
+            parent,
+        ) {
+            walk.skip(directory, err);
+        }
+    }
+
+    fn visit_directory<'s>(
+        &'s self,
+        scope: &Scope<'s>,
+        walk: &'s Walk<'s>,
+        root: usize,
+        directory: PathBuf,
+        depth: usize,
+        parent: Option<Arc<IgnoreLevel>>,
+    ) -> Result<(), io::Error> {
+        let modified = fs::metadata(&directory)?.modified()?;
+        // Entries found from another root were filtered by its settings
+        let previous = walk
+            .previous
+            .and_then(|previous| previous.directories.get(&directory))
+            .filter(|entry| entry.root == root);
+        let cached = previous.filter(|entry| {
+            entry.modified == modified
+                && entry.broken.iter().all(|link| broken(link).is_some())
+        });
+
+        let settings = &walk.roots[root];
+        let min_depth = settings.min_depth.or(self.min_depth).unwrap_or(0);
+        let is_project = depth >= min_depth
+            && match cached {
+                Some(entry) => entry.project,
+                None => self.markers.is_project(&directory)?,
+            };
+        if is_project {
+            let entry = Entry {
+                modified,
+                project: true,
+                children: Vec::new(),
+                broken: Vec::new(),
+                ignores: Vec::new(),
+                root,
+            };
+            let _ = walk.visited.send(Ok((directory, entry)));
+            return Ok(());
+        }
+        let (matchers, ignores) = if self.respect_ignore {
+            read_ignore_files(&directory)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        // Which subdirectories are left to search changes with the ignore
+        // rules of the directory and of its parents alike
+        let stale = parent.as_deref().is_some_and(|parent| parent.stale)
+            || previous.is_some_and(|entry| entry.ignores != ignores);
+        let cached = cached.filter(|_| !stale);
+        let level = Arc::new(IgnoreLevel {
+            matchers,
+            stale,
+            parent,
+        });
+        // A directory with entries that could not be read is left out of the
+        // index, so the next search reads it again instead of trusting it
+        let mut complete = true;
+        let (children, broken_links) = match cached {
+            Some(entry) => {
+                for link in &entry.broken {
+                    if let Some(error) = broken(link) {
+                        walk.skip(link.clone(), error);
+                    }
+                }
+                (entry.children.clone(), entry.broken.clone())
+            }
+            None => {
+                let mut children = Vec::new();
+                let mut broken_links = Vec::new();
+                for entry in fs::read_dir(&directory)? {
+                    let (entry, file_type) = match entry.and_then(|entry| {
+                        Ok((entry.path(), entry.file_type()?))
+                    }) {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            walk.skip(directory.clone(), err);
+                            complete = false;
+                            continue;
+                        }
+                    };
+                    if file_type.is_symlink() {
+                        if !self.follow_symlinks {
+                            debug!("not following symlink: {:?}", entry);
+                            continue;
+                        }
+                        if let Some(error) = broken(&entry) {
+                            walk.skip(entry.clone(), error);
+                            broken_links.push(entry);
+                            continue;
+                        }
+                    }
+                    if !entry.is_dir() {
+                        continue;
+                    }
+                    if self.is_ignored(&entry, &level) {
+                        debug!("skipping ignored directory: {:?}", entry);
+                        continue;
+                    }
+                    let relative =
+                        entry.strip_prefix(&settings.path).unwrap_or(&entry);
+                    if self.exclude.is_excluded(relative)
+                        || settings.exclude.is_excluded(relative)
+                    {
+                        debug!("skipping excluded directory: {:?}", entry);
+                        continue;
+                    }
+                    children.push(entry);
+                }
+                (children, broken_links)
+            }
+        };
+        let max_depth = settings.max_depth.or(self.max_depth);
+        if max_depth.is_none_or(|max_depth| depth < max_depth) {
+            for child in children.iter().cloned() {
+                let level = level.clone();
+                let metadata = fs::symlink_metadata(&child);
+                if metadata.as_ref().is_ok_and(|m| m.file_type().is_symlink()) {
+                    let link = (root, child, depth + 1, level);
+                    walk.links.lock().unwrap().push(link);
+                    continue;
+                }
+                if !walk.claim(&child, metadata) {
+                    continue;
+                }
+                scope.spawn(move |scope| {
+                    self.search_directory(
+                        scope,
+                        walk,
+                        root,
+                        child,
+                        depth + 1,
+                        Some(level),
+                    )
+                });
+            }
+        }
+        if complete {
+            let entry = Entry {
+                modified,
+                project: false,
+                children,
+                broken: broken_links,
+                ignores,
+                root,
+            };
+            let _ = walk.visited.send(Ok((directory, entry)));
+        }
+
+        Ok(())
+    }
+
+    fn is_ignored(&self, path: &Path, level: &IgnoreLevel) -> bool {
+        let mut current = Some(level);
+        while let Some(level) = current {
+            for matcher in &level.matchers {
+                let matched = matcher.matched(path, true);
+                if matched.is_ignore() {
+                    return true;
+                }
+                if matched.is_whitelist() {
+                    return false;
+                }
+            }
+            current = level.parent.as_deref();
+        }
+        self.global_ignore.matched(path, true).is_ignore()
+    }
+}
+
+/// The error to report for a symlink, unless its target exists
+fn broken(link: &Path) -> Option<io::Error> {
+    fs::metadata(link).err().map(|err| {
+        io::Error::new(err.kind(), format!("broken symbolic link: {}", err))
+    })
+}
+
+/// The ignore rules of a single directory, chained to those of its parents
+struct IgnoreLevel {
+    matchers: Vec<Gitignore>,
+    /// Whether the rules changed since the previous index, so the cached
+    /// children of the directories below cannot be trusted
+    stale: bool,
+    parent: Option<Arc<IgnoreLevel>>,
+}
+
+/// The ignore rules of a directory, along with when each ignore file was last
+/// modified, `None` for those missing
+fn read_ignore_files(
+    directory: &Path,
+) -> (Vec<Gitignore>, Vec<Option<SystemTime>>) {
+    let mut matchers = Vec::new();
+    let mut modified = Vec::new();
+    for name in IGNORE_FILES {
+        let path = directory.join(name);
+        let metadata = fs::metadata(&path).ok().filter(|m| m.is_file());
+        modified.push(metadata.as_ref().and_then(|m| m.modified().ok()));
+        if metadata.is_none() {
+            continue;
+        }
+        trace!("reading ignore file: {:?}", path);
+        let (matcher, err) = Gitignore::new(&path);
+        if let Some(err) = err {
+            warn!("could not fully parse {:?}: {}", path, err);
+        }
+        matchers.push(matcher);
+    }
+    (matchers, modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    fn project(root: &Path, path: &str) -> PathBuf {
+        let project = root.join(path);
+        fs::create_dir_all(project.join(".git")).unwrap();
+        project
+    }
+
+    #[test]
+    fn respects_ignore_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let kept = project(root, "kept");
+        let git_ignored = project(root, "node_modules/dependency");
+        let ignored = project(root, "build/output");
+        let finder_ignored = project(root, "nested/archive/old");
+        let whitelisted = project(root, "nested/vendor");
+        fs::write(root.join(".gitignore"), "node_modules\nvendor\n").unwrap();
+        fs::write(root.join(".ignore"), "build/\n").unwrap();
+        fs::write(
+            root.join("nested/.projectfinderignore"),
+            "archive\n!vendor\n",
+        )
+        .unwrap();
+
+        let directories = Searcher::new(Markers::default(), true, 0)
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap()
+            .projects;
+
+        assert!(directories.contains(&kept));
+        assert!(directories.contains(&whitelisted));
+        assert!(!directories.contains(&git_ignored));
+        assert!(!directories.contains(&ignored));
+        assert!(!directories.contains(&finder_ignored));
+    }
+
+    #[test]
+    fn ignore_files_can_be_disabled() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let git_ignored = project(root, "node_modules/dependency");
+        fs::write(root.join(".gitignore"), "node_modules\n").unwrap();
+
+        let directories = Searcher::new(Markers::default(), false, 0)
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap()
+            .projects;
+
+        assert!(directories.contains(&git_ignored));
+    }
+
+    #[test]
+    fn results_are_ordered_by_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let b = project(root, "second/b");
+        let a = project(root, "second/a");
+        let z = project(root, "first/z");
+        let y = project(root, "first/nested/y");
+
+        for threads in [1, 4] {
+            let directories = Searcher::new(Markers::default(), true, threads)
+                .index(
+                    vec![root.join("first").into(), root.join("second").into()],
+                    None,
+                )
+                .unwrap()
+                .projects;
+            assert_eq!(
+                directories,
+                vec![y.clone(), z.clone(), a.clone(), b.clone()]
+            );
+        }
+    }
+
+    #[test]
+    fn exclude_and_max_depth() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let kept = project(root, "kept");
+        let deep = project(root, "a/b/deep");
+        let by_name = project(root, "a/target/built");
+        let by_path = project(root, "archive/old");
+        let nested_archive = project(root, "a/archive/old");
+
+        let exclude =
+            Exclude::compile(&["target".to_string(), "archive/*".to_string()])
+                .unwrap();
+        let projects = Searcher::new(Markers::default(), true, 0)
+            .exclude(exclude)
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap()
+            .projects;
+        assert!(projects.contains(&kept));
+        assert!(projects.contains(&deep));
+        assert!(projects.contains(&nested_archive));
+        assert!(!projects.contains(&by_name));
+        assert!(!projects.contains(&by_path));
+
+        let projects = Searcher::new(Markers::default(), true, 0)
+            .max_depth(Some(2))
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap()
+            .projects;
+        assert!(projects.contains(&kept));
+        assert!(projects.contains(&by_path));
+        assert!(!projects.contains(&deep));
+    }
+
+    #[test]
+    fn root_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let shallow = project(root, "code/shallow");
+        let within = project(root, "code/shallow/within");
+        let deep = project(root, "code/a/b/deep");
+        let vendored = project(root, "vendor/lib/dep");
+        let excluded = project(root, "vendor/old/dep");
+
+        let projects = Searcher::new(Markers::default(), true, 0)
+            .max_depth(Some(2))
+            .index(
+                vec![
+                    Root::new(root.join("code"))
+                        .min_depth(Some(2))
+                        .max_depth(Some(3)),
+                    Root::new(root.join("vendor")).exclude(
+                        Exclude::compile(&["old".to_string()]).unwrap(),
+                    ),
+                ],
+                None,
+            )
+            .unwrap()
+            .projects;
+        assert_eq!(projects, vec![deep, within, vendored]);
+        assert!(!projects.contains(&shallow));
+        assert!(!projects.contains(&excluded));
+    }
+
+    #[test]
+    fn overlapping_roots_find_projects_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let a = project(root, "code/a");
+        let b = project(root, "code/work/b");
+        std::os::unix::fs::symlink(root.join("code"), root.join("link"))
+            .unwrap();
+
+        let directories = Searcher::new(Markers::default(), true, 4)
+            .index(
+                vec![
+                    root.join("code").into(),
+                    root.join("code/work").into(),
+                    root.join("link").into(),
+                ],
+                None,
+            )
+            .unwrap()
+            .projects;
+        assert_eq!(directories, vec![a, b]);
+    }
+
+    #[test]
+    fn unreadable_paths_are_skipped() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let kept = project(root, "code/kept");
+        std::os::unix::fs::symlink(root.join("gone"), root.join("code/broken"))
+            .unwrap();
+
+        let index = Searcher::new(Markers::default(), true, 4)
+            .index(
+                vec![root.join("code").into(), root.join("missing").into()],
+                None,
+            )
+            .unwrap();
+        assert_eq!(index.projects, vec![kept.clone()]);
+        let skipped: Vec<&PathBuf> =
+            index.skipped.iter().map(|skip| &skip.path).collect();
+        assert_eq!(
+            skipped,
+            vec![&root.join("code/broken"), &root.join("missing")]
+        );
+        assert!(!index.directories.contains_key(&root.join("missing")));
+        assert_eq!(
+            index.directories[&root.join("code")].broken,
+            vec![root.join("code/broken")]
+        );
+
+        let cached = Searcher::new(Markers::default(), true, 4)
+            .index(vec![root.join("code").into()], Some(&index))
+            .unwrap();
+        assert_eq!(cached.skipped.len(), 1);
+
+        // Creating the target of the link does not modify the directory, but
+        // still brings it back into the search
+        project(root, "gone");
+        let cached = Searcher::new(Markers::default(), true, 4)
+            .index(vec![root.join("code").into()], Some(&cached))
+            .unwrap();
+        assert!(cached.skipped.is_empty());
+        assert_eq!(cached.projects, vec![root.join("code/broken"), kept]);
+    }
+
+    #[test]
+    fn symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let api = project(root, "code/api");
+        let linked = project(root, "elsewhere/linked");
+        let symlink = |target: &str, link: &str| {
+            std::os::unix::fs::symlink(root.join(target), root.join(link))
+                .unwrap()
+        };
+        fs::create_dir(root.join("code/nested")).unwrap();
+        symlink("code", "code/nested/cycle");
+        symlink("code/api", "code/alias");
+        symlink("elsewhere", "code/outside");
+
+        let search = |follow_symlinks| {
+            Searcher::new(Markers::default(), true, 4)
+                .follow_symlinks(follow_symlinks)
+                .index(vec![root.join("code").into()], None)
+                .unwrap()
+        };
+        let followed = search(true);
+        assert_eq!(
+            followed.projects,
+            vec![api.clone(), root.join("code/outside/linked")]
+        );
+        assert!(followed.skipped.is_empty());
+        assert_ne!(linked, root.join("code/outside/linked"));
+        assert_eq!(search(false).projects, vec![api]);
+    }
+
+    #[test]
+    fn index_reuses_unmodified_directories() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let a = project(root, "a");
+        let searcher = Searcher::new(Markers::default(), true, 1);
+        let mut index = searcher
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap();
+        assert_eq!(index.projects, vec![a.clone()]);
+
+        // A directory the stale index believes is a project stays one as long
+        // as the directory is not modified
+        let fake = root.join("fake");
+        fs::create_dir(&fake).unwrap();
+        let modified = fs::metadata(&fake).unwrap().modified().unwrap();
+        index.directories.insert(
+            fake.clone(),
+            Entry {
+                modified,
+                project: true,
+                children: Vec::new(),
+                broken: Vec::new(),
+                ignores: Vec::new(),
+                root: 0,
+            },
+        );
+        let root_modified = fs::metadata(root).unwrap().modified().unwrap();
+        index.directories.get_mut(root).unwrap().modified = root_modified;
+        index
+            .directories
+            .get_mut(root)
+            .unwrap()
+            .children
+            .push(fake.clone());
+
+        let reindexed = searcher
+            .index(vec![root.to_path_buf().into()], Some(&index))
+            .unwrap();
+        assert_eq!(reindexed.projects, vec![a.clone(), fake.clone()]);
+
+        let rescanned = searcher
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap();
+        assert_eq!(rescanned.projects, vec![a]);
+    }
+
+    #[test]
+    fn cache_hits_skip_marker_checks() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let unmarked = project(root, "unmarked");
+        let searcher = Searcher::new(Markers::default(), true, 1);
+        let mut index = searcher
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap();
+        assert_eq!(index.projects, vec![unmarked.clone()]);
+
+        // With its modification carried over, the entry is trusted as is
+        // instead of looking for the removed marker
+        fs::remove_dir(unmarked.join(".git")).unwrap();
+        let modified = fs::metadata(&unmarked).unwrap().modified().unwrap();
+        index.directories.get_mut(&unmarked).unwrap().modified = modified;
+
+        let reindexed = searcher
+            .index(vec![root.to_path_buf().into()], Some(&index))
+            .unwrap();
+        assert_eq!(reindexed.projects, vec![unmarked]);
+
+        let rescanned = searcher
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap();
+        assert!(rescanned.projects.is_empty());
+    }
+
+    #[test]
+    fn editing_ignore_files_searches_again() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let kept = project(root, "kept");
+        let dependency = project(root, "nested/node_modules/dependency");
+        let gitignore = root.join(".gitignore");
+        fs::write(&gitignore, "").unwrap();
+        let searcher = Searcher::new(Markers::default(), true, 1);
+        let index = searcher
+            .index(vec![root.to_path_buf().into()], None)
+            .unwrap();
+        assert_eq!(index.projects, vec![kept.clone(), dependency]);
+
+        // Editing the file in place leaves every directory unmodified
+        let modified = fs::metadata(root).unwrap().modified().unwrap();
+        fs::write(&gitignore, "node_modules\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&gitignore)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(fs::metadata(root).unwrap().modified().unwrap(), modified);
+
+        let reindexed = searcher
+            .index(vec![root.to_path_buf().into()], Some(&index))
+            .unwrap();
+        assert_eq!(reindexed.projects, vec![kept]);
+    }
+
+    #[test]
+    fn cache_is_kept_per_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let shallow = project(root, "code/work/shallow");
+        let deep = project(root, "code/work/a/deep");
+        let search = |roots: Vec<Root>, previous: Option<&Index>| {
+            Searcher::new(Markers::default(), true, 1)
+                .index(roots, previous)
+                .unwrap()
+        };
+        let nested = search(
+            vec![
+                Root::new(root.join("code")),
+                Root::new(root.join("code/work")).min_depth(Some(2)),
+            ],
+            None,
+        );
+        assert_eq!(nested.projects, vec![deep.clone()]);
+
+        // Searched from its parent, the directory below the nested root gets
+        // the settings of the parent rather than the cached ones
+        let parent = search(vec![Root::new(root.join("code"))], Some(&nested));
+        assert_eq!(parent.projects, vec![deep, shallow]);
+    }
+}
@@ -0,0 +1,156 @@
+//! Shell integration printed by `shell-init`. A process cannot change the
+//! directory of the shell that started it, so the shell is given a wrapper
+//! function that passes a `--cd-file` to the picker and changes into the
+//! directory written to it.
+
+use std::str::FromStr;
+
+use clap::crate_name;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nushell,
+}
+
+impl Shell {
+    pub const NAMES: [&'static str; 4] = ["bash", "zsh", "fish", "nushell"];
+
+    /// Script defining a function called `command` that opens the picker and
+    /// changes into the chosen project, optionally bound to a `ctrl-<key>`
+    pub fn init(&self, command: &str, bind: Option<char>) -> String {
+        let binary = crate_name!();
+        let mut script = match self {
+            Self::Bash | Self::Zsh => format!(
+                r#"{command}() {{
+    local cd_file dir code
+    cd_file="$(mktemp)" || return
+    command {binary} --cd-file "$cd_file" "$@"
+    code=$?
+    dir="$(cat -- "$cd_file")"
+    rm -f -- "$cd_file"
+    if [ -n "$dir" ]; then
+        cd -- "$dir" || return
+    fi
+    return "$code"
+}}
+"#
+            ),
+            Self::Fish => format!(
+                r#"function {command}
+    set -l cd_file (mktemp); or return
+    command {binary} --cd-file $cd_file $argv
+    set -l code $status
+    set -l dir (cat -- $cd_file)
+    rm -f -- $cd_file
+    if test -n "$dir"
+        cd -- $dir
+    end
+    return $code
+end
+"#
+            ),
+            Self::Nushell => format!(
+                r#"def --env --wrapped {command} [...args] {{
+    let cd_file = (mktemp -t)
+    ^{binary} --cd-file $cd_file ...$args
+    let dir = (open --raw $cd_file | str trim)
+    rm -f $cd_file
+    if $dir != "" {{
+        cd $dir
+    }}
+}}
+"#
+            ),
+        };
+        if let Some(key) = bind {
+            script += &match self {
+                Self::Bash => format!("bind -x '\"\\C-{key}\": {command}'\n"),
+                Self::Zsh => format!(
+                    r#"_{command}_widget() {{
+    {command} </dev/tty
+    zle reset-prompt
+}}
+zle -N _{command}_widget
+bindkey '^{upper}' _{command}_widget
+"#,
+                    upper = key.to_ascii_uppercase()
+                ),
+                Self::Fish => format!(
+                    "bind \\c{key} '{command}; commandline -f repaint'\n"
+                ),
+                Self::Nushell => format!(
+                    r#"$env.config = ($env.config | upsert keybindings (
+    $env.config.keybindings | append {{
+        name: {command}
+        modifier: control
+        keycode: char_{key}
+        mode: [emacs vi_normal vi_insert]
+        event: {{ send: executehostcommand cmd: "{command}" }}
+    }}
+))
+"#
+                ),
+            };
+        }
+        script
+    }
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            "nushell" | "nu" => Ok(Self::Nushell),
+            name => Err(Error::UnhandledAction(name.to_string())),
+        }
+    }
+}
+
+/// Parse a key binding written as `ctrl-<letter>`, the only kind of binding
+/// every supported shell can express the same way
+pub fn parse_binding(binding: &str) -> Result<char, Error> {
+    let invalid = || Error::InvalidKeyBinding(binding.to_string());
+    let key = binding
+        .to_ascii_lowercase()
+        .strip_prefix("ctrl-")
+        .ok_or_else(invalid)?
+        .to_string();
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(key), None) if key.is_ascii_lowercase() => Ok(key),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings() {
+        assert_eq!(parse_binding("ctrl-g").unwrap(), 'g');
+        assert_eq!(parse_binding("Ctrl-G").unwrap(), 'g');
+        assert!(parse_binding("g").is_err());
+        assert!(parse_binding("ctrl-gg").is_err());
+        assert!(parse_binding("ctrl-1").is_err());
+
+        assert!(Shell::Bash
+            .init("pf", Some('g'))
+            .ends_with("bind -x '\"\\C-g\": pf'\n"));
+        assert!(Shell::Zsh.init("pf", Some('g')).contains("bindkey '^G'"));
+        assert!(Shell::Fish.init("pf", Some('g')).contains("bind \\cg"));
+        assert!(Shell::Nushell
+            .init("pf", None)
+            .starts_with("def --env --wrapped pf [...args]"));
+        assert!(!Shell::Nushell.init("pf", None).contains("keybindings"));
+    }
+}
@@ -1,24 +1,47 @@
 use termion::event::Key;
 use termion::input::TermRead;
-use termion::raw::IntoRawMode;
 use termion::{
     clear, color,
     cursor::{self, DetectCursorPos},
     style, terminal_size,
 };
-use toml::map::IterMut;
 use tracing::info;
 
-use std::collections::HashMap;
-use std::fmt::Display;
-use std::io::{self, Read, Write};
+use std::fmt::{self, Display};
+use std::io::{self, BufRead, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, Once, OnceLock};
+use std::thread;
 
-use crate::error::Error;
+use crate::{error::Error, matcher::Match, query};
 
-pub struct Window<R, W: Write> {
-    stdin: R,
+/// Drawn in front of the selected path
+const MARKER: &str = "\u{25B6} ";
+/// Style of the selected row
+const SELECTED: style::Bold = style::Bold;
+
+/// Window receiving the keys typed. A read of the terminal cannot be
+/// interrupted, so a single thread reads keys for every window the process
+/// opens; a reader per window would swallow the first key typed into the
+/// next one.
+static KEYS: Mutex<Option<Sender<io::Result<Event>>>> = Mutex::new(None);
+static KEY_READER: Once = Once::new();
+
+/// Something the window has to react to, either typed by the user or sent
+/// from another thread through [`Window::sender`]
+pub enum Event {
+    Key(Key),
+    /// Replace the paths to choose from
+    Paths(Vec<PathBuf>),
+    /// Lines previewing a path
+    Preview(PathBuf, Vec<String>),
+}
+
+pub struct Window<W: Write> {
+    events: Receiver<io::Result<Event>>,
+    sender: Sender<io::Result<Event>>,
     stdout: W,
     path_surface: Surface,
     width: u16,
@@ -26,43 +49,90 @@ pub struct Window<R, W: Write> {
     help: Vec<String>,
     input: String,
     paths: Vec<PathBuf>,
-    filtered_paths: Vec<PathBuf>,
+    /// Paths matching the input along with the positions of the matched
+    /// characters
+    filtered_paths: Vec<(PathBuf, Vec<usize>)>,
+    /// Index of the selected path within the filtered paths
+    selected: usize,
+    /// Index of the filtered path shown on the bottom row
+    offset: usize,
+    highlight: Style,
+    show_preview: bool,
+    /// The latest preview along with the path it previews
+    preview: Option<(PathBuf, Vec<String>)>,
 }
 
-impl<R: Iterator<Item = std::io::Result<Key>>, W: Write> Window<R, W> {
+impl<W: Write> Window<W> {
+    /// Keep the paths the filter matches, ranked by [`query::rank`]
     pub fn filter_paths<P>(&mut self, filter: P)
     where
-        P: FnMut(&PathBuf) -> bool,
+        P: FnMut(&PathBuf) -> Option<Match>,
     {
-        self.filtered_paths =
-            self.paths.clone().into_iter().filter(filter).collect();
+        self.filtered_paths = query::rank(&self.paths, filter);
+        self.selected = self
+            .selected
+            .min(self.filtered_paths.len().saturating_sub(1));
+    }
+    /// Move the selection by an amount of rows, where positive amounts move
+    /// up towards worse matches
+    pub fn move_selection(&mut self, rows: isize) {
+        self.selected = self
+            .selected
+            .saturating_add_signed(rows)
+            .min(self.filtered_paths.len().saturating_sub(1));
+    }
+    pub fn select_first(&mut self) {
+        self.selected = 0;
+    }
+    pub fn select_last(&mut self) {
+        self.selected = self.filtered_paths.len().saturating_sub(1);
+    }
+    /// Amount of rows available to show paths
+    pub fn page_size(&self) -> usize {
+        usize::from(self.path_surface.row_end - self.path_surface.row_start) + 1
+    }
+    /// Show or hide the preview pane, which takes the right half of the
+    /// window
+    pub fn toggle_preview(&mut self) {
+        self.show_preview = !self.show_preview;
+        self.path_surface.col_end = if self.show_preview {
+            self.width / 2
+        } else {
+            self.width
+        };
+    }
+    /// The path a preview is needed for, which is the selected path while
+    /// the preview pane is shown
+    pub fn preview_path(&self) -> Option<PathBuf> {
+        self.show_preview.then(|| self.get_selected()).flatten()
+    }
+    pub fn set_preview(&mut self, path: PathBuf, lines: Vec<String>) {
+        self.preview = Some((path, lines));
+    }
+    pub fn set_highlight(&mut self, highlight: Style) {
+        self.highlight = highlight;
     }
     pub fn draw_paths(&mut self) -> Result<(), Error> {
+        let page = self.page_size();
+        self.offset =
+            scroll(self.offset, self.selected, self.filtered_paths.len(), page);
         write!(self.stdout, "{}", cursor::Hide)?;
         write!(self.stdout, "{}", style::Reset)?;
-        if self.filtered_paths.len() as u16 <= self.path_surface.row_start {
-            write!(
-                self.stdout,
-                "{}{}[{}/{}]",
-                cursor::Goto(1, self.path_surface.row_end + 1),
-                style::Bold,
-                self.filtered_paths.len(),
-                self.filtered_paths.len(),
-            )?;
-        } else {
-            write!(
-                self.stdout,
-                "{}{}[{}/{}]",
-                cursor::Goto(1, self.path_surface.row_end + 1),
-                style::Bold,
-                self.filtered_paths.len(),
-                self.path_surface.row_end - self.path_surface.row_start
-            )?;
-        }
+        // The counter is measured rather than asking the terminal for the
+        // cursor position, as the answer would race with the key reader
+        let counter =
+            format!("[{}/{}]", self.filtered_paths.len(), self.paths.len());
+        write!(
+            self.stdout,
+            "{}{}{}",
+            cursor::Goto(1, self.path_surface.row_end + 1),
+            style::Bold,
+            counter,
+        )?;
         write!(self.stdout, "{}", color::Fg(color::LightBlack))?;
         let mut line_acc = "".to_string();
-        let (col, _) = self.stdout.cursor_pos()?;
-        for _ in 0..(self.path_surface.col_end - col) {
+        let col = counter.chars().count() as u16 + 1;
+        for _ in 0..self.width.saturating_sub(col) {
             line_acc = format!("{}\u{2014}", line_acc);
         }
         write!(self.stdout, "{}", line_acc)?;
@@ -75,27 +145,89 @@ impl<R: Iterator<Item = std::io::Result<Key>>, W: Write> Window<R, W> {
                 clear::CurrentLine,
             )?;
         }
-        for (i, path) in self.filtered_paths.iter().enumerate() {
-            let line = self.path_surface.row_end - (i as u16);
-            if line < self.path_surface.row_start {
-                break;
-            }
-
+        // One column is kept free for the scrollbar
+        let width = usize::from(self.path_surface.col_end)
+            .saturating_sub(MARKER.chars().count() + 1);
+        let visible = self
+            .filtered_paths
+            .iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(page);
+        for (row, (i, (path, positions))) in visible.enumerate() {
+            let line = self.path_surface.row_end - (row as u16);
+            let (marker, base) = if i == self.selected {
+                (MARKER, SELECTED.to_string())
+            } else {
+                ("  ", String::new())
+            };
             write!(
                 self.stdout,
-                "{}{}{}{}",
+                "{}{}{}{}{}{}",
                 cursor::Goto(1, line),
                 clear::CurrentLine,
-                path.to_str()
-                    .ok_or(Error::PathUnicodeError(path.to_path_buf()))?,
+                base,
+                marker,
+                highlight(path, positions, width, &self.highlight, &base),
                 style::Reset,
             )?;
         }
+        if let Some(thumb) =
+            scrollbar(self.offset, self.filtered_paths.len(), page)
+        {
+            for row in 0..page {
+                let symbol = if thumb.contains(&row) {
+                    '\u{2503}'
+                } else {
+                    '\u{2502}'
+                };
+                write!(
+                    self.stdout,
+                    "{}{}{}{}",
+                    cursor::Goto(
+                        self.path_surface.col_end,
+                        self.path_surface.row_end - (row as u16)
+                    ),
+                    color::Fg(color::LightBlack),
+                    symbol,
+                    style::Reset,
+                )?;
+            }
+        }
+        if self.show_preview {
+            self.draw_preview()?;
+        }
         write!(self.stdout, "{}", cursor::Show)?;
         self.stdout.flush()?;
         Ok(())
     }
 
+    /// Draw the preview of the selected path to the right of the paths,
+    /// or a placeholder while it is being made
+    fn draw_preview(&mut self) -> Result<(), Error> {
+        let selected = self.get_selected();
+        let lines: &[String] = match (&self.preview, &selected) {
+            (Some((path, lines)), Some(selected)) if path == selected => lines,
+            (_, Some(_)) => &[String::from("\u{2026}")],
+            (_, None) => &[],
+        };
+        let col = self.path_surface.col_end + 1;
+        let width = usize::from(self.width.saturating_sub(col + 1));
+        for row in 0..self.page_size() {
+            let line = lines.get(row).map_or("", String::as_str);
+            write!(
+                self.stdout,
+                "{}{}\u{2502}{} {}{}",
+                cursor::Goto(col, self.path_surface.row_start + row as u16),
+                color::Fg(color::LightBlack),
+                style::Reset,
+                line.chars().take(width).collect::<String>(),
+                clear::UntilNewline,
+            )?;
+        }
+        Ok(())
+    }
+
     pub fn register_help(
         &mut self,
         key: Key,
@@ -166,38 +298,48 @@ impl<R: Iterator<Item = std::io::Result<Key>>, W: Write> Window<R, W> {
 
     pub fn push(&mut self, ch: char) {
         self.input.push(ch);
+        self.selected = 0;
     }
     pub fn pop(&mut self) -> Option<char> {
+        self.selected = 0;
         self.input.pop()
     }
+    pub fn set_input(&mut self, input: String) {
+        self.input = input;
+        self.selected = 0;
+    }
     pub fn get_input(&self) -> &str {
         &self.input
     }
     pub fn get_selected(&self) -> Option<PathBuf> {
-        self.filtered_paths.get(0).cloned()
+        self.filtered_paths
+            .get(self.selected)
+            .map(|(path, _)| path.clone())
+    }
+    pub fn set_paths(&mut self, paths: Vec<PathBuf>) {
+        self.paths = paths;
+    }
+    pub fn sender(&self) -> Sender<io::Result<Event>> {
+        self.sender.clone()
     }
 }
-impl<R: Iterator<Item = std::io::Result<Key>>, W: Write> Iterator
-    for Window<R, W>
-{
-    type Item = Result<Key, Error>;
+impl<W: Write> Iterator for Window<W> {
+    type Item = Result<Event, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Err(err) = self.before_next_iter() {
             return Some(Err(err));
         }
-        self.stdin
-            .next()
-            .map(|val| val.map_err(|err| Error::from(err)))
+        self.events.recv().ok().map(|val| val.map_err(Error::from))
     }
 }
-impl<R: Read, W: Write> Window<termion::input::Keys<R>, W> {
-    pub fn init(
+impl<W: Write> Window<W> {
+    pub fn init<R: Read + Send + 'static>(
         stdin: R,
         mut stdout: W,
         paths: Vec<PathBuf>,
     ) -> Result<Self, Error> {
-        let (width, height) = terminal_size()?;
+        let (width, height) = window_size(&mut stdout)?;
         info!("window created");
         write!(
             stdout,
@@ -207,21 +349,43 @@ impl<R: Read, W: Write> Window<termion::input::Keys<R>, W> {
             cursor::Goto(1, 1),
         )?;
         stdout.flush()?;
+        let (sender, events) = mpsc::channel();
+        if let Ok(mut keys) = KEYS.lock() {
+            *keys = Some(sender.clone());
+        }
+        KEY_READER.call_once(|| {
+            thread::spawn(move || {
+                for key in stdin.keys() {
+                    if let Ok(Some(keys)) = KEYS.lock().as_deref() {
+                        let _ = keys.send(key.map(Event::Key));
+                    }
+                }
+            });
+        });
         Ok(Self {
-            stdin: stdin.keys(),
+            events,
+            sender,
             stdout,
             path_surface: Surface::new(1, 2, width, height - 2),
             width,
             height,
             help: Vec::new(),
             input: String::new(),
-            paths: paths.clone(),
-            filtered_paths: paths,
+            filtered_paths: paths
+                .iter()
+                .map(|path| (path.clone(), Vec::new()))
+                .collect(),
+            paths,
+            selected: 0,
+            offset: 0,
+            highlight: Style::default(),
+            show_preview: false,
+            preview: None,
         })
     }
 }
 
-impl<R, W: Write> Drop for Window<R, W> {
+impl<W: Write> Drop for Window<W> {
     fn drop(&mut self) {
         info!("window dropped");
         write!(
@@ -235,8 +399,60 @@ impl<R, W: Write> Drop for Window<R, W> {
     }
 }
 
+/// Ask to pick one of a few options by number, as a plain question on the
+/// terminal rather than a [`Window`], so nothing reads keys or queries the
+/// terminal before the window that follows. An empty answer picks the first
+/// option and the end of the input none.
+pub fn pick<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    question: &str,
+    options: &[&str],
+) -> Result<Option<usize>, Error> {
+    for (i, option) in options.iter().enumerate() {
+        writeln!(output, "{:>3}) {}", i + 1, option)?;
+    }
+    loop {
+        write!(output, "{} [1]: ", question)?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            writeln!(output)?;
+            return Ok(None);
+        }
+        match answer.trim() {
+            "" => return Ok(Some(0)),
+            answer => match answer.parse::<usize>() {
+                Ok(n) if (1..=options.len()).contains(&n) => {
+                    return Ok(Some(n - 1))
+                }
+                _ => writeln!(output, "Pick 1 to {}", options.len())?,
+            },
+        }
+    }
+}
+
+/// Size of the terminal, asked from the terminal itself when stdout is not
+/// the terminal, such as when the chosen path is captured by a shell. Its
+/// answer would go to the key reader once that runs, so it is only asked
+/// before the first window starts the reader and kept for later windows.
+fn window_size<W: Write>(stdout: &mut W) -> io::Result<(u16, u16)> {
+    static ASKED: OnceLock<(u16, u16)> = OnceLock::new();
+    if let Ok(size) = terminal_size() {
+        return Ok(size);
+    }
+    if let Some(size) = ASKED.get() {
+        return Ok(*size);
+    }
+    write!(stdout, "{}", cursor::Goto(u16::MAX, u16::MAX))?;
+    stdout.flush()?;
+    let size = stdout.cursor_pos()?;
+    Ok(*ASKED.get_or_init(|| size))
+}
+
 struct Surface {
     row_start: u16,
+    #[allow(dead_code)]
     col_start: u16,
     row_end: u16,
     col_end: u16,
@@ -251,3 +467,227 @@ impl Surface {
         }
     }
 }
+
+/// Offset of the first visible path, scrolled as little as possible to keep
+/// the selection in view without leaving rows empty when paths could fill
+/// them
+fn scroll(offset: usize, selected: usize, len: usize, page: usize) -> usize {
+    let offset = if selected < offset {
+        selected
+    } else if selected >= offset + page {
+        selected + 1 - page
+    } else {
+        offset
+    };
+    offset.min(len.saturating_sub(page))
+}
+
+/// Rows covered by the thumb of the scrollbar, counted from the bottom, or
+/// `None` if every path fits on the page
+fn scrollbar(
+    offset: usize,
+    len: usize,
+    page: usize,
+) -> Option<std::ops::Range<usize>> {
+    if len <= page || page == 0 {
+        return None;
+    }
+    let size = (page * page / len).max(1);
+    let start = if offset + page >= len {
+        page - size
+    } else {
+        (offset * page / len).min(page - size)
+    };
+    Some(start..start + size)
+}
+
+/// Render a path within a width, highlighting the characters at the given
+/// positions before going back to the base style. Paths that are too long are
+/// cut from the left, as the end of a path tells the most about it.
+fn highlight(
+    path: &Path,
+    positions: &[usize],
+    width: usize,
+    style: &Style,
+    base: &str,
+) -> String {
+    let chars: Vec<char> = path.to_string_lossy().chars().collect();
+    let (skip, mut line) = if chars.len() > width {
+        (chars.len() + 1 - width, "\u{2026}".to_string())
+    } else {
+        (0, String::new())
+    };
+    for (i, ch) in chars.iter().enumerate().skip(skip) {
+        if positions.binary_search(&i).is_ok() {
+            line += &format!("{}{}{}{}", style, ch, style::Reset, base);
+        } else {
+            line.push(*ch);
+        }
+    }
+    line
+}
+
+/// Terminal attributes and colors written as a comma separated list, such as
+/// `bold,underline,fg:green,bg:#303030`. Colors are either a name like `red`
+/// or `light-blue`, an ANSI color number or a hexadecimal RGB value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style(String);
+
+impl Default for Style {
+    fn default() -> Self {
+        Self(format!("{}{}", style::Bold, color::Fg(color::Green)))
+    }
+}
+
+impl Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Style {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidStyle(spec.to_string());
+        let mut codes = String::new();
+        for attribute in spec.split(',').map(str::trim) {
+            let code = match attribute {
+                "" => continue,
+                "bold" => style::Bold.to_string(),
+                "faint" => style::Faint.to_string(),
+                "italic" => style::Italic.to_string(),
+                "underline" => style::Underline.to_string(),
+                "reverse" => style::Invert.to_string(),
+                attribute => match attribute.split_once(':') {
+                    Some(("fg", color)) => {
+                        let color = parse_color(color).ok_or_else(invalid)?;
+                        color::Fg(color.as_ref()).to_string()
+                    }
+                    Some(("bg", color)) => {
+                        let color = parse_color(color).ok_or_else(invalid)?;
+                        color::Bg(color.as_ref()).to_string()
+                    }
+                    Some(_) => return Err(invalid()),
+                    None => {
+                        let color =
+                            parse_color(attribute).ok_or_else(invalid)?;
+                        color::Fg(color.as_ref()).to_string()
+                    }
+                },
+            };
+            codes += &code;
+        }
+        Ok(Self(codes))
+    }
+}
+
+fn parse_color(color: &str) -> Option<Box<dyn color::Color>> {
+    Some(match color {
+        "black" => Box::new(color::Black),
+        "red" => Box::new(color::Red),
+        "green" => Box::new(color::Green),
+        "yellow" => Box::new(color::Yellow),
+        "blue" => Box::new(color::Blue),
+        "magenta" => Box::new(color::Magenta),
+        "cyan" => Box::new(color::Cyan),
+        "white" => Box::new(color::White),
+        "light-black" => Box::new(color::LightBlack),
+        "light-red" => Box::new(color::LightRed),
+        "light-green" => Box::new(color::LightGreen),
+        "light-yellow" => Box::new(color::LightYellow),
+        "light-blue" => Box::new(color::LightBlue),
+        "light-magenta" => Box::new(color::LightMagenta),
+        "light-cyan" => Box::new(color::LightCyan),
+        "light-white" => Box::new(color::LightWhite),
+        color => match color.strip_prefix('#') {
+            Some(hex) if hex.len() == 6 => {
+                let value = u32::from_str_radix(hex, 16).ok()?;
+                Box::new(color::Rgb(
+                    (value >> 16) as u8,
+                    (value >> 8) as u8,
+                    value as u8,
+                ))
+            }
+            Some(_) => return None,
+            None => Box::new(color::AnsiValue(color.parse().ok()?)),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_truncates_from_the_left() {
+        let style = Style(String::from("*"));
+        let reset = style::Reset.to_string();
+        assert_eq!(
+            highlight(Path::new("/code/api"), &[6, 8], 20, &style, ""),
+            format!("/code/*a{reset}p*i{reset}")
+        );
+        assert_eq!(
+            highlight(Path::new("/code/api"), &[1, 8], 5, &style, "+"),
+            format!("\u{2026}/ap*i{reset}+")
+        );
+    }
+
+    #[test]
+    fn scroll_follows_selection() {
+        assert_eq!(scroll(0, 3, 100, 10), 0);
+        assert_eq!(scroll(0, 10, 100, 10), 1);
+        assert_eq!(scroll(50, 99, 100, 10), 90);
+        assert_eq!(scroll(50, 20, 100, 10), 20);
+        // Shrinking results scroll back so no rows are left empty
+        assert_eq!(scroll(50, 5, 8, 10), 0);
+        assert_eq!(scroll(50, 14, 15, 10), 5);
+    }
+
+    #[test]
+    fn scrollbar_thumb() {
+        assert_eq!(scrollbar(0, 10, 10), None);
+        assert_eq!(scrollbar(0, 40, 10), Some(0..2));
+        assert_eq!(scrollbar(20, 40, 10), Some(5..7));
+        assert_eq!(scrollbar(30, 40, 10), Some(8..10));
+        assert_eq!(scrollbar(500, 1000, 10), Some(5..6));
+    }
+
+    #[test]
+    fn pick_by_number() {
+        let options = ["api", "code"];
+        let pick = |input: &str| {
+            let mut output = Vec::new();
+            let picked =
+                pick(input.as_bytes(), &mut output, "Workspace", &options)
+                    .unwrap();
+            (picked, String::from_utf8(output).unwrap())
+        };
+        assert_eq!(pick("2\n").0, Some(1));
+        assert_eq!(pick("\n").0, Some(0));
+        assert_eq!(pick("").0, None);
+        let (picked, output) = pick("3\nx\n 1 \n");
+        assert_eq!(picked, Some(0));
+        assert_eq!(output.matches("Pick 1 to 2").count(), 2);
+        assert!(output.starts_with("  1) api\n  2) code\nWorkspace [1]: "));
+    }
+
+    #[test]
+    fn parse_style() {
+        assert_eq!(
+            "bold, fg:red,bg:#ff0080".parse::<Style>().unwrap(),
+            Style(format!(
+                "{}{}{}",
+                style::Bold,
+                color::Fg(color::Red),
+                color::Bg(color::Rgb(255, 0, 128))
+            ))
+        );
+        assert_eq!(
+            "blue".parse::<Style>().unwrap(),
+            Style(color::Fg(color::Blue).to_string())
+        );
+        assert!("fg:purple".parse::<Style>().is_err());
+        assert!("blink".parse::<Style>().is_err());
+    }
+}
@@ -0,0 +1,547 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    marker::{Marker, Markers},
+    search::{Exclude, Root, Searcher},
+};
+
+/// A named group of directories searched for projects, written in the config
+/// either as a bare array of directories or as a table with its settings:
+///
+/// ```toml
+/// [workspaces]
+/// legacy = ["/home/me/code"]
+///
+/// [workspaces.work]
+/// directories = ["/home/me/work"]
+/// description = "Everything paid for"
+/// exclude = ["node_modules", "archive/*"]
+/// markers = [".git/", "Cargo.toml"]
+/// max_depth = 3
+/// min_depth = 1
+/// respect_ignore = false
+/// follow_symlinks = false
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Definition", into = "Definition")]
+pub struct Workspace {
+    pub directories: Vec<Directory>,
+    pub description: Option<String>,
+    /// Globs of directories to skip, see [`Exclude`]
+    pub exclude: Vec<String>,
+    /// Markers of a project, a `.git` directory when empty
+    pub markers: Vec<Marker>,
+    /// Deepest level below a directory searched for projects, where the
+    /// directory itself is at level 0
+    pub max_depth: Option<usize>,
+    /// Shallowest level below a directory a project is found at
+    pub min_depth: Option<usize>,
+    pub respect_ignore: bool,
+    /// Whether symlinked directories are searched, see
+    /// [`Searcher::follow_symlinks`]
+    pub follow_symlinks: bool,
+}
+
+impl Default for Workspace {
+    fn default() -> Self {
+        Self {
+            directories: Vec::new(),
+            description: None,
+            exclude: Vec::new(),
+            markers: Vec::new(),
+            max_depth: None,
+            min_depth: None,
+            respect_ignore: true,
+            follow_symlinks: true,
+        }
+    }
+}
+
+/// A directory of a workspace, written as its path or as a table adding to
+/// the settings of its workspace for it alone:
+///
+/// ```toml
+/// directories = [
+///     "/home/me/work",
+///     { path = "/home/me/vendor", exclude = ["archive/**"], max_depth = 1 },
+/// ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "DirectoryDefinition", into = "DirectoryDefinition")]
+pub struct Directory {
+    pub path: PathBuf,
+    /// Globs of directories to skip in addition to those of the workspace
+    pub exclude: Vec<String>,
+    /// Depths replacing those of the workspace
+    pub max_depth: Option<usize>,
+    pub min_depth: Option<usize>,
+}
+
+impl From<PathBuf> for Directory {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            exclude: Vec::new(),
+            max_depth: None,
+            min_depth: None,
+        }
+    }
+}
+
+impl Directory {
+    pub fn root(&self) -> Result<Root, Error> {
+        Ok(Root::new(self.path.clone())
+            .exclude(Exclude::compile(&self.exclude)?)
+            .max_depth(self.max_depth)
+            .min_depth(self.min_depth))
+    }
+}
+
+/// Settings given on the command line for a single search, taking
+/// precedence over those of every workspace and directory searched
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// Globs of directories to skip in addition to the configured ones
+    pub exclude: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub min_depth: Option<usize>,
+}
+
+impl Overrides {
+    pub fn is_empty(&self) -> bool {
+        self.exclude.is_empty()
+            && self.max_depth.is_none()
+            && self.min_depth.is_none()
+    }
+}
+
+impl Workspace {
+    /// Check what deserializing cannot, so a broken workspace is reported
+    /// before searching it rather than halfway through the search
+    pub fn validate(&self, name: &str) -> Result<(), Error> {
+        for directory in &self.directories {
+            if !directory.path.is_absolute() {
+                return Err(Error::RelativeDirectoryError(
+                    name.to_string(),
+                    directory.path.clone(),
+                ));
+            }
+            let min_depth = directory.min_depth.or(self.min_depth);
+            let max_depth = directory.max_depth.or(self.max_depth);
+            if let (Some(min_depth), Some(max_depth)) = (min_depth, max_depth) {
+                if min_depth > max_depth {
+                    return Err(Error::InvalidDepth(
+                        name.to_string(),
+                        min_depth,
+                        max_depth,
+                    ));
+                }
+            }
+        }
+        self.roots()?;
+        self.searcher(1).map(|_| ())
+    }
+
+    pub fn contains(&self, directory: &Path) -> bool {
+        self.directories
+            .iter()
+            .any(|existing| existing.path == directory)
+    }
+
+    /// The directory within or containing a directory, comparing canonical
+    /// paths so a symlink cannot hide an overlap. The directory itself is
+    /// expected to be canonical.
+    pub fn overlapping(&self, directory: &Path) -> Option<&PathBuf> {
+        self.directories.iter().map(|existing| &existing.path).find(
+            |existing| {
+                let existing = fs::canonicalize(existing)
+                    .unwrap_or_else(|_| existing.to_path_buf());
+                existing.starts_with(directory)
+                    || directory.starts_with(&existing)
+            },
+        )
+    }
+
+    /// Apply settings given for a single search, where a depth given
+    /// replaces the depths of every directory as well
+    pub fn apply(&mut self, overrides: &Overrides) {
+        self.exclude.extend(overrides.exclude.iter().cloned());
+        if overrides.max_depth.is_some() {
+            self.max_depth = overrides.max_depth;
+            for directory in &mut self.directories {
+                directory.max_depth = None;
+            }
+        }
+        if overrides.min_depth.is_some() {
+            self.min_depth = overrides.min_depth;
+            for directory in &mut self.directories {
+                directory.min_depth = None;
+            }
+        }
+    }
+
+    pub fn roots(&self) -> Result<Vec<Root>, Error> {
+        self.directories.iter().map(Directory::root).collect()
+    }
+
+    pub fn searcher(&self, threads: usize) -> Result<Searcher, Error> {
+        let markers = if self.markers.is_empty() {
+            Markers::default()
+        } else {
+            Markers::compile(&self.markers)?
+        };
+        Ok(Searcher::new(markers, self.respect_ignore, threads)
+            .exclude(Exclude::compile(&self.exclude)?)
+            .max_depth(self.max_depth)
+            .min_depth(self.min_depth)
+            .follow_symlinks(self.follow_symlinks))
+    }
+}
+
+/// What to search when the current directory is within several workspaces
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Inference {
+    /// The workspace with the deepest directory containing it
+    #[default]
+    Specific,
+    /// Ask which workspace to search
+    Prompt,
+    /// Every workspace containing it at once
+    Union,
+}
+
+/// The workspaces with a directory containing a path, most specific first:
+/// ordered by how deep their deepest such directory is, and by name when
+/// equally deep so the order never depends on how the config is written
+pub fn infer<'w>(
+    workspaces: &'w BTreeMap<String, Workspace>,
+    path: &Path,
+) -> Vec<&'w String> {
+    let mut candidates: Vec<(usize, &String)> = workspaces
+        .iter()
+        .filter_map(|(name, workspace)| {
+            workspace
+                .directories
+                .iter()
+                .filter(|directory| path.starts_with(&directory.path))
+                .map(|directory| directory.path.components().count())
+                .max()
+                .map(|depth| (depth, name))
+        })
+        .collect();
+    // A stable sort keeps the names of equally deep workspaces in order
+    candidates.sort_by(|(a, _), (b, _)| b.cmp(a));
+    candidates.into_iter().map(|(_, name)| name).collect()
+}
+
+/// The ways a workspace can be written in the config
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Definition {
+    Directories(Vec<Directory>),
+    Table(Table),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Table {
+    #[serde(default)]
+    directories: Vec<Directory>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    markers: Vec<Marker>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_depth: Option<usize>,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    respect_ignore: bool,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    follow_symlinks: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+impl From<Definition> for Workspace {
+    fn from(definition: Definition) -> Self {
+        match definition {
+            Definition::Directories(directories) => Self {
+                directories,
+                ..Self::default()
+            },
+            Definition::Table(table) => Self {
+                directories: table.directories,
+                description: table.description,
+                exclude: table.exclude,
+                markers: table.markers,
+                max_depth: table.max_depth,
+                min_depth: table.min_depth,
+                respect_ignore: table.respect_ignore,
+                follow_symlinks: table.follow_symlinks,
+            },
+        }
+    }
+}
+
+impl From<Workspace> for Definition {
+    /// Keep workspaces without settings in the shorter array form
+    fn from(workspace: Workspace) -> Self {
+        let has_settings = workspace.description.is_some()
+            || !workspace.exclude.is_empty()
+            || !workspace.markers.is_empty()
+            || workspace.max_depth.is_some()
+            || workspace.min_depth.is_some()
+            || !workspace.respect_ignore
+            || !workspace.follow_symlinks;
+        if !has_settings {
+            return Self::Directories(workspace.directories);
+        }
+        Self::Table(Table {
+            directories: workspace.directories,
+            description: workspace.description,
+            exclude: workspace.exclude,
+            markers: workspace.markers,
+            max_depth: workspace.max_depth,
+            min_depth: workspace.min_depth,
+            respect_ignore: workspace.respect_ignore,
+            follow_symlinks: workspace.follow_symlinks,
+        })
+    }
+}
+
+/// The ways a directory can be written in the config
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum DirectoryDefinition {
+    Path(PathBuf),
+    Table(DirectoryTable),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectoryTable {
+    path: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_depth: Option<usize>,
+}
+
+impl From<DirectoryDefinition> for Directory {
+    fn from(definition: DirectoryDefinition) -> Self {
+        match definition {
+            DirectoryDefinition::Path(path) => path.into(),
+            DirectoryDefinition::Table(table) => Self {
+                path: table.path,
+                exclude: table.exclude,
+                max_depth: table.max_depth,
+                min_depth: table.min_depth,
+            },
+        }
+    }
+}
+
+impl From<Directory> for DirectoryDefinition {
+    /// Keep directories without settings as a bare path
+    fn from(directory: Directory) -> Self {
+        if directory.exclude.is_empty()
+            && directory.max_depth.is_none()
+            && directory.min_depth.is_none()
+        {
+            return Self::Path(directory.path);
+        }
+        Self::Table(DirectoryTable {
+            path: directory.path,
+            exclude: directory.exclude,
+            max_depth: directory.max_depth,
+            min_depth: directory.min_depth,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    type Workspaces = BTreeMap<String, Workspace>;
+
+    #[test]
+    fn both_forms() {
+        let workspaces: Workspaces = toml::from_str(
+            r#"
+            legacy = ["/code"]
+
+            [work]
+            directories = ["/work", { path = "/vendor", exclude = ["old"], min_depth = 1 }]
+            description = "Work"
+            max_depth = 2
+            respect_ignore = false
+            follow_symlinks = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            workspaces["legacy"],
+            Workspace {
+                directories: vec![PathBuf::from("/code").into()],
+                ..Workspace::default()
+            }
+        );
+        assert_eq!(
+            workspaces["work"].directories,
+            [
+                PathBuf::from("/work").into(),
+                Directory {
+                    path: PathBuf::from("/vendor"),
+                    exclude: vec!["old".to_string()],
+                    max_depth: None,
+                    min_depth: Some(1),
+                }
+            ]
+        );
+        assert_eq!(workspaces["work"].description.as_deref(), Some("Work"));
+        assert_eq!(workspaces["work"].max_depth, Some(2));
+        assert!(!workspaces["work"].respect_ignore);
+        assert!(!workspaces["work"].follow_symlinks);
+
+        let written = toml::to_string(&workspaces).unwrap();
+        assert!(written.starts_with("legacy = [\"/code\"]\n"));
+        assert_eq!(toml::from_str::<Workspaces>(&written).unwrap(), workspaces);
+    }
+
+    #[test]
+    fn invalid_workspaces() {
+        assert!(toml::from_str::<Workspaces>("a = 1").is_err());
+        assert!(toml::from_str::<Workspaces>("a = { directory = [] }").is_err());
+
+        let relative = Workspace {
+            directories: vec![PathBuf::from("code").into()],
+            ..Workspace::default()
+        };
+        assert!(matches!(
+            relative.validate("a"),
+            Err(Error::RelativeDirectoryError(..))
+        ));
+        let exclude = Workspace {
+            exclude: vec!["[".to_string()],
+            ..Workspace::default()
+        };
+        assert!(matches!(
+            exclude.validate("a"),
+            Err(Error::InvalidExclude(..))
+        ));
+        assert!(toml::from_str::<Workspaces>(
+            r#"a = [{ path = "/code", depth = 1 }]"#
+        )
+        .is_err());
+        let directory_exclude = Workspace {
+            directories: vec![Directory {
+                exclude: vec!["[".to_string()],
+                ..Directory::from(PathBuf::from("/code"))
+            }],
+            ..Workspace::default()
+        };
+        assert!(matches!(
+            directory_exclude.validate("a"),
+            Err(Error::InvalidExclude(..))
+        ));
+        let depth = Workspace {
+            directories: vec![Directory {
+                min_depth: Some(3),
+                ..Directory::from(PathBuf::from("/code"))
+            }],
+            max_depth: Some(2),
+            ..Workspace::default()
+        };
+        assert!(matches!(
+            depth.validate("a"),
+            Err(Error::InvalidDepth(_, 3, 2))
+        ));
+    }
+
+    #[test]
+    fn overrides() {
+        let mut workspace = Workspace {
+            directories: vec![Directory {
+                max_depth: Some(1),
+                min_depth: Some(1),
+                ..Directory::from(PathBuf::from("/code"))
+            }],
+            exclude: vec!["vendor".to_string()],
+            max_depth: Some(3),
+            ..Workspace::default()
+        };
+        workspace.apply(&Overrides {
+            exclude: vec!["archive/**".to_string()],
+            max_depth: Some(5),
+            min_depth: None,
+        });
+        assert_eq!(workspace.exclude, ["vendor", "archive/**"]);
+        assert_eq!(workspace.max_depth, Some(5));
+        assert_eq!(workspace.directories[0].max_depth, None);
+        assert_eq!(workspace.directories[0].min_depth, Some(1));
+    }
+
+    #[test]
+    fn most_specific_first() {
+        let workspace = |directories: &[&str]| Workspace {
+            directories: directories
+                .iter()
+                .map(|directory| PathBuf::from(directory).into())
+                .collect(),
+            ..Workspace::default()
+        };
+        let workspaces = Workspaces::from([
+            ("all".to_string(), workspace(&["/code"])),
+            ("b".to_string(), workspace(&["/code/work", "/other"])),
+            ("a".to_string(), workspace(&["/code/work"])),
+            ("elsewhere".to_string(), workspace(&["/other"])),
+        ]);
+        assert_eq!(
+            infer(&workspaces, Path::new("/code/work/api")),
+            ["a", "b", "all"]
+        );
+        assert_eq!(infer(&workspaces, Path::new("/code")), ["all"]);
+        assert!(infer(&workspaces, Path::new("/codex")).is_empty());
+    }
+
+    #[test]
+    fn overlapping_directories() {
+        let workspace = Workspace {
+            directories: vec![
+                PathBuf::from("/code").into(),
+                PathBuf::from("/work").into(),
+            ],
+            ..Workspace::default()
+        };
+        let overlap =
+            |directory: &str| workspace.overlapping(directory.as_ref());
+        assert_eq!(overlap("/code/api"), Some(&PathBuf::from("/code")));
+        assert_eq!(overlap("/"), Some(&PathBuf::from("/code")));
+        assert_eq!(overlap("/workshop"), None);
+    }
+}